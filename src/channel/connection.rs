@@ -0,0 +1,371 @@
+use std::{
+    pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use hyper::{
+    body::Incoming,
+    rt::{Read, ReadBuf, ReadBufCursor, Write},
+};
+use tokio::time::Sleep;
+
+use crate::channel::stats::{ChannelMetrics, ConnectionStats};
+
+/// The builder of the HTTP/2 connections of a channel.
+pub(crate) type Http2ConnectionBuilder = hyper::client::conn::http2::Builder<hyper_util::rt::TokioExecutor>;
+
+/// The [hyper::client::conn::http2::SendRequest] of a connection, shared with the task driving the connection, which
+/// takes it out once the connection is idle, so that hyper closes the connection gracefully.
+#[cfg(feature = "singleton-channel")]
+pub(crate) type SharedSendRequest =
+    Arc<std::sync::Mutex<Option<hyper::client::conn::http2::SendRequest<tonic::body::Body>>>>;
+
+/// The maximum age of the connections of a channel, after which no new requests are sent over a connection, along with
+/// the optional grace period given to its in-flight requests before it is forcibly closed.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MaxConnectionAge {
+    pub(crate) age: Duration,
+    pub(crate) grace: Option<Duration>,
+}
+
+impl MaxConnectionAge {
+    pub(crate) fn new(age: Option<Duration>, grace: Option<Duration>) -> Result<Option<Self>, crate::ConfigError> {
+        if age.is_some_and(|age| age.is_zero()) {
+            return Err(crate::ConfigError::new("max_connection_age", "must be greater than 0"));
+        }
+
+        Ok(age.map(|age| Self { age, grace }))
+    }
+}
+
+/// The activity of a single HTTP/2 connection, shared between its I/O, the services sending requests over it and the
+/// background task driving it.
+#[derive(Debug)]
+pub(crate) struct ConnectionActivity {
+    created_at: Instant,
+    last_active_millis: AtomicU64,
+    pending_requests: AtomicUsize,
+    draining: AtomicBool,
+    closed: AtomicBool,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    metrics: Arc<ChannelMetrics>,
+}
+
+impl ConnectionActivity {
+    /// Create the [ConnectionActivity] of a new connection, registering the connection with the given [ChannelMetrics].
+    pub(crate) fn new(metrics: Arc<ChannelMetrics>) -> Arc<Self> {
+        let activity = Arc::new(Self {
+            created_at: Instant::now(),
+            last_active_millis: AtomicU64::new(0),
+            pending_requests: AtomicUsize::new(0),
+            draining: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            metrics,
+        });
+
+        activity.metrics.register_connection(&activity);
+        activity
+    }
+
+    pub(crate) fn metrics(&self) -> &ChannelMetrics {
+        &self.metrics
+    }
+
+    pub(crate) fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            in_flight_streams: self.pending_requests(),
+            age: self.created_at.elapsed(),
+            draining: self.is_draining(),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn created_at(&self) -> Instant {
+        self.created_at
+    }
+
+    fn touch(&self) {
+        let millis = self.created_at.elapsed().as_millis() as u64;
+        self.last_active_millis.fetch_max(millis, Ordering::Relaxed);
+    }
+
+    fn record_sent(&self, bytes: usize) {
+        self.touch();
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.metrics.record_sent(bytes);
+    }
+
+    fn record_received(&self, bytes: usize) {
+        self.touch();
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.metrics.record_received(bytes);
+    }
+
+    pub(crate) fn last_active(&self) -> Instant {
+        self.created_at + Duration::from_millis(self.last_active_millis.load(Ordering::Relaxed))
+    }
+
+    /// Mark the connection as draining, after the server announced that it is going away or the connection was found
+    /// closed, so that no new requests are routed to it.
+    pub(crate) fn drain(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Mark the connection as closed with the result of driving it, recording its error if it failed. A closed
+    /// connection is no longer reported in the [crate::ChannelStats] of its channel.
+    pub(crate) fn close(&self, result: Result<(), hyper::Error>) {
+        self.drain();
+        self.closed.store(true, Ordering::Relaxed);
+
+        if let Err(err) = result {
+            self.metrics.record_error(&err);
+        }
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// The number of requests currently pending on the connection.
+    pub(crate) fn pending_requests(&self) -> usize {
+        self.pending_requests.load(Ordering::Relaxed)
+    }
+
+    /// Mark a request as pending until the returned [PendingRequest] is dropped, which prevents the connection from
+    /// being considered idle while the server is processing the request without transferring any data.
+    pub(crate) fn pending_request(self: &Arc<Self>) -> PendingRequest {
+        self.pending_requests.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+
+        PendingRequest { activity: self.clone() }
+    }
+}
+
+/// A guard marking a request as pending on a connection, created via [ConnectionActivity::pending_request].
+pub(crate) struct PendingRequest {
+    activity: Arc<ConnectionActivity>,
+}
+
+impl Drop for PendingRequest {
+    fn drop(&mut self) {
+        self.activity.touch();
+        self.activity.pending_requests.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl PendingRequest {
+    /// Keep the request pending until the body of the given response is finished.
+    pub(crate) fn hold_until_end(self, response: http::Response<Incoming>) -> http::Response<tonic::body::Body> {
+        response.map(|body| tonic::body::Body::new(PendingBody::new(body, self)))
+    }
+}
+
+/// A response body wrapper holding the [PendingRequest] of its request until the body is finished, being its end of
+/// stream, its trailers or an error, or until the body is dropped, so that a streaming response counts towards the load
+/// of its connection for as long as the stream is open.
+struct PendingBody {
+    inner: Incoming,
+    pending_request: Option<PendingRequest>,
+}
+
+impl PendingBody {
+    fn new(inner: Incoming, pending_request: PendingRequest) -> Self {
+        let pending_request = (!inner.is_end_stream()).then_some(pending_request);
+
+        Self { inner, pending_request }
+    }
+}
+
+impl HttpBody for PendingBody {
+    type Data = Bytes;
+
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+
+        let finished = match &poll {
+            Poll::Ready(Some(Ok(frame))) => frame.is_trailers() || self.inner.is_end_stream(),
+            Poll::Ready(_) => true,
+            Poll::Pending => false,
+        };
+
+        if finished {
+            self.pending_request = None;
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// An I/O wrapper recording every read and write of a connection into its [ConnectionActivity], and failing them once
+/// the connection has exceeded its [MaxConnectionAge] along with its grace period.
+pub(crate) struct TrackedIo<IO> {
+    io: IO,
+    activity: Arc<ConnectionActivity>,
+    close_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<IO> TrackedIo<IO> {
+    pub(crate) fn new(io: IO, activity: Arc<ConnectionActivity>, max_connection_age: Option<MaxConnectionAge>) -> Self {
+        let close_deadline = max_connection_age.and_then(|max_connection_age| {
+            let grace = max_connection_age.grace?;
+            Some(Box::pin(tokio::time::sleep_until(
+                (activity.created_at + max_connection_age.age + grace).into(),
+            )))
+        });
+
+        Self {
+            io,
+            activity,
+            close_deadline,
+        }
+    }
+
+    fn poll_close_deadline(&mut self, cx: &mut Context<'_>) -> Result<(), std::io::Error> {
+        if let Some(ref mut close_deadline) = self.close_deadline {
+            if close_deadline.as_mut().poll(cx).is_ready() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "connection exceeded its maximum age and grace period",
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<IO: Read + Unpin> Read for TrackedIo<IO> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        mut buf: ReadBufCursor<'_>,
+    ) -> Poll<Result<(), std::io::Error>> {
+        let this = self.get_mut();
+        this.poll_close_deadline(cx)?;
+
+        // SAFETY: the unfilled part of the cursor is only handed to the inner I/O for reading into, which never
+        // uninitializes any bytes
+        let mut read_buf = ReadBuf::uninit(unsafe { buf.as_mut() });
+        let read = match Pin::new(&mut this.io).poll_read(cx, read_buf.unfilled()) {
+            Poll::Ready(Ok(())) => read_buf.filled().len(),
+            poll => return poll,
+        };
+
+        // SAFETY: the inner I/O has initialized the bytes it has read
+        unsafe { buf.advance(read) };
+        this.activity.record_received(read);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<IO: Write + Unpin> Write for TrackedIo<IO> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, std::io::Error>> {
+        let this = self.get_mut();
+        this.poll_close_deadline(cx)?;
+        let poll = Pin::new(&mut this.io).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(written)) = poll {
+            this.activity.record_sent(written);
+        }
+
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, std::io::Error>> {
+        let this = self.get_mut();
+        this.poll_close_deadline(cx)?;
+        let poll = Pin::new(&mut this.io).poll_write_vectored(cx, bufs);
+
+        if let Poll::Ready(Ok(written)) = poll {
+            this.activity.record_sent(written);
+        }
+
+        poll
+    }
+}
+
+/// Drive the given HTTP/2 connection future to completion. If an idle timeout is set, the connection is closed
+/// gracefully once no requests have been pending on it and no data has been transferred over it for the idle timeout,
+/// by marking it as draining and dropping its [SharedSendRequest], after which hyper sends a GOAWAY frame and closes
+/// the connection.
+#[cfg(feature = "singleton-channel")]
+pub(crate) async fn drive_connection<F: Future<Output = Result<(), hyper::Error>>>(
+    connection: F,
+    send_request: SharedSendRequest,
+    activity: Arc<ConnectionActivity>,
+    idle_timeout: Option<Duration>,
+) {
+    let Some(idle_timeout) = idle_timeout else {
+        activity.close(connection.await);
+        return;
+    };
+
+    let mut connection = std::pin::pin!(connection);
+
+    loop {
+        let deadline = match activity.pending_requests() {
+            0 => activity.last_active() + idle_timeout,
+            _ => Instant::now() + idle_timeout,
+        };
+
+        if let Ok(result) = tokio::time::timeout_at(deadline.into(), connection.as_mut()).await {
+            activity.close(result);
+            return;
+        }
+
+        // Requests are marked as pending while holding the lock, so none can be sent once it was taken out
+        let mut send_request = send_request.lock().expect("send request mutex was poisoned");
+
+        if activity.pending_requests() == 0 && activity.last_active().elapsed() >= idle_timeout {
+            activity.drain();
+            drop(send_request.take());
+            break;
+        }
+    }
+
+    activity.close(connection.await);
+}
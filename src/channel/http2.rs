@@ -0,0 +1,115 @@
+use std::time::Duration;
+
+use crate::ConfigError;
+
+/// The largest HTTP/2 flow control window size.
+const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+/// The range of allowed HTTP/2 frame sizes.
+const FRAME_SIZE_RANGE: std::ops::RangeInclusive<u32> = (1 << 14)..=((1 << 24) - 1);
+
+/// Configuration for the HTTP/2 connections of a channel, shared by all channel kinds. This struct is cheaply
+/// [Clone]-able and implements [Default], as all its settings are optional, with [hyper]'s defaults used for unset ones.
+/// Like with [hyper], the keep-alive timeout and keep-alive while idle settings have no effect unless a keep-alive
+/// interval is set.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Http2Config {
+    pub initial_stream_window_size: Option<u32>,
+    pub initial_connection_window_size: Option<u32>,
+    pub initial_max_send_streams: Option<usize>,
+    pub adaptive_window: Option<bool>,
+    pub max_frame_size: Option<u32>,
+    pub max_header_list_size: Option<u32>,
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    pub keep_alive_interval: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
+    pub keep_alive_timeout: Option<Duration>,
+    pub keep_alive_while_idle: Option<bool>,
+    pub max_concurrent_reset_streams: Option<usize>,
+    pub max_pending_accept_reset_streams: Option<usize>,
+}
+
+impl Http2Config {
+    /// Validate this [Http2Config], emitting a [ConfigError] for the first invalid option, with its field path prefixed
+    /// by `http2.`.
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(size) = self.initial_stream_window_size {
+            validate_window_size("http2.initial_stream_window_size", size)?;
+        }
+
+        if let Some(size) = self.initial_connection_window_size {
+            validate_window_size("http2.initial_connection_window_size", size)?;
+        }
+
+        if let Some(size) = self.max_frame_size {
+            if !FRAME_SIZE_RANGE.contains(&size) {
+                return Err(ConfigError::new(
+                    "http2.max_frame_size",
+                    format!(
+                        "must be between {} and {}",
+                        FRAME_SIZE_RANGE.start(),
+                        FRAME_SIZE_RANGE.end()
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn apply_to_connection_builder(&self, builder: &mut crate::channel::connection::Http2ConnectionBuilder) {
+        if let Some(size) = self.initial_stream_window_size {
+            builder.initial_stream_window_size(size);
+        }
+
+        if let Some(size) = self.initial_connection_window_size {
+            builder.initial_connection_window_size(size);
+        }
+
+        if let Some(initial) = self.initial_max_send_streams {
+            builder.initial_max_send_streams(initial);
+        }
+
+        if let Some(enabled) = self.adaptive_window {
+            builder.adaptive_window(enabled);
+        }
+
+        if let Some(size) = self.max_frame_size {
+            builder.max_frame_size(size);
+        }
+
+        if let Some(size) = self.max_header_list_size {
+            builder.max_header_list_size(size);
+        }
+
+        if let Some(interval) = self.keep_alive_interval {
+            builder.keep_alive_interval(interval);
+        }
+
+        if let Some(timeout) = self.keep_alive_timeout {
+            builder.keep_alive_timeout(timeout);
+        }
+
+        if let Some(enabled) = self.keep_alive_while_idle {
+            builder.keep_alive_while_idle(enabled);
+        }
+
+        if let Some(max) = self.max_concurrent_reset_streams {
+            builder.max_concurrent_reset_streams(max);
+        }
+
+        if let Some(max) = self.max_pending_accept_reset_streams {
+            builder.max_pending_accept_reset_streams(max);
+        }
+    }
+}
+
+fn validate_window_size(field: &str, size: u32) -> Result<(), ConfigError> {
+    if size > MAX_WINDOW_SIZE {
+        return Err(ConfigError::new(field, format!("must not exceed {MAX_WINDOW_SIZE}")));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::{Arc, Mutex, Weak},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use http::{Request, Response};
+use tokio::time::Instant;
+use tonic::body::Body;
+use tower::{BoxError, Service, ServiceExt};
+
+use crate::{
+    ConfigError, GrpcRoutingKey, KeyedGrpcConnectorFactory, SingletonGrpcChannel, SingletonGrpcChannelBuilder,
+};
+
+/// A builder for a [KeyedGrpcChannel].
+#[derive(Debug, Clone)]
+pub struct KeyedGrpcChannelBuilder {
+    channel_builder: SingletonGrpcChannelBuilder,
+    idle_timeout: Option<Duration>,
+}
+
+impl KeyedGrpcChannelBuilder {
+    /// Create a new [KeyedGrpcChannelBuilder] from the [SingletonGrpcChannelBuilder] used to build the channel of
+    /// every [GrpcRoutingKey].
+    pub fn new(channel_builder: SingletonGrpcChannelBuilder) -> Self {
+        Self {
+            channel_builder,
+            idle_timeout: None,
+        }
+    }
+
+    /// Set a [Duration] after which the channel of a [GrpcRoutingKey] that had no requests sent to it is evicted,
+    /// closing its connection. Idle channels are evicted by a background task, so they are closed even if no further
+    /// requests are sent through the [KeyedGrpcChannel]. Without an idle timeout, channels are only evicted via
+    /// [KeyedGrpcChannel::evict].
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Build a [KeyedGrpcChannel] backed by the given [KeyedGrpcConnectorFactory].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any option set on this builder is invalid. Use [KeyedGrpcChannelBuilder::try_build] to handle invalid
+    /// options as a [ConfigError] instead.
+    pub fn build(self, connector_factory: KeyedGrpcConnectorFactory) -> KeyedGrpcChannel {
+        match self.try_build(connector_factory) {
+            Ok(channel) => channel,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Build a [KeyedGrpcChannel] backed by the given [KeyedGrpcConnectorFactory], emitting a [ConfigError] for the
+    /// first invalid option that was set on this builder.
+    pub fn try_build(self, connector_factory: KeyedGrpcConnectorFactory) -> Result<KeyedGrpcChannel, ConfigError> {
+        if self.idle_timeout.is_some_and(|timeout| timeout.is_zero()) {
+            return Err(ConfigError::new("idle_timeout", "must be greater than 0"));
+        }
+
+        Ok(KeyedGrpcChannel {
+            shared: Arc::new(KeyedShared {
+                connector_factory,
+                channel_builder: self.channel_builder,
+                idle_timeout: self.idle_timeout,
+                channels: Mutex::new(KeyedChannels::default()),
+            }),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct KeyedShared {
+    connector_factory: KeyedGrpcConnectorFactory,
+    channel_builder: SingletonGrpcChannelBuilder,
+    idle_timeout: Option<Duration>,
+    channels: Mutex<KeyedChannels>,
+}
+
+#[derive(Debug, Default)]
+struct KeyedChannels {
+    channels: HashMap<GrpcRoutingKey, CachedChannel>,
+    eviction_started: bool,
+}
+
+#[derive(Debug)]
+struct CachedChannel {
+    channel: SingletonGrpcChannel,
+    last_used: Instant,
+}
+
+impl KeyedChannels {
+    fn evict_idle(&mut self, idle_timeout: Duration) {
+        let now = Instant::now();
+        self.channels
+            .retain(|_, cached| now.duration_since(cached.last_used) < idle_timeout);
+    }
+}
+
+impl KeyedShared {
+    fn channel(self: &Arc<Self>, key: &GrpcRoutingKey) -> Result<SingletonGrpcChannel, BoxError> {
+        let now = Instant::now();
+        let mut channels = self.channels.lock().expect("keyed channel mutex was poisoned");
+
+        if let Some(idle_timeout) = self.idle_timeout {
+            if !channels.eviction_started {
+                channels.eviction_started = true;
+                tokio::task::spawn(evict_idle(Arc::downgrade(self), idle_timeout));
+            }
+        }
+
+        if let Some(cached) = channels.channels.get_mut(key) {
+            cached.last_used = now;
+            return Ok(cached.channel.clone());
+        }
+
+        let connector = self.connector_factory.build(key)?;
+        let channel = self.channel_builder.clone().try_build(connector)?;
+        channels.channels.insert(
+            key.clone(),
+            CachedChannel {
+                channel: channel.clone(),
+                last_used: now,
+            },
+        );
+
+        Ok(channel)
+    }
+}
+
+/// Periodically evict the channels that exceeded the idle timeout, until the [KeyedGrpcChannel] is dropped.
+async fn evict_idle(shared: Weak<KeyedShared>, idle_timeout: Duration) {
+    let period = (idle_timeout / 2).max(Duration::from_millis(1));
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+
+    loop {
+        interval.tick().await;
+
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+
+        shared
+            .channels
+            .lock()
+            .expect("keyed channel mutex was poisoned")
+            .evict_idle(idle_timeout);
+    }
+}
+
+/// A gRPC channel [Service] compatible with [tonic] that routes every request to a [SingletonGrpcChannel] selected by
+/// the [GrpcRoutingKey] in the request's extensions. The channel of a key is built lazily via a
+/// [KeyedGrpcConnectorFactory] and cached until it is idle for longer than the configured idle timeout, which allows a
+/// single [KeyedGrpcChannel] to serve a whole fleet of microVMs. Requests without a [GrpcRoutingKey] fail. This struct
+/// is cheaply [Clone]-able, with all clones sharing the same cache.
+#[derive(Debug, Clone)]
+pub struct KeyedGrpcChannel {
+    shared: Arc<KeyedShared>,
+}
+
+impl KeyedGrpcChannel {
+    /// Evict the cached channel of the given [GrpcRoutingKey], closing its connection once all of its in-flight
+    /// requests complete. Returns whether a channel was cached for the key.
+    pub fn evict(&self, key: &GrpcRoutingKey) -> bool {
+        self.shared
+            .channels
+            .lock()
+            .expect("keyed channel mutex was poisoned")
+            .channels
+            .remove(key)
+            .is_some()
+    }
+}
+
+impl Service<Request<Body>> for KeyedGrpcChannel {
+    type Response = Response<Body>;
+
+    type Error = BoxError;
+
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let channel = match request.extensions().get::<GrpcRoutingKey>() {
+            Some(key) => self.shared.channel(key),
+            None => Err("No GrpcRoutingKey was specified in the extensions of a request to a keyed channel".into()),
+        };
+
+        Box::pin(async move { channel?.ready_oneshot().await?.call(request).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::GrpcConnectorBuilder;
+
+    use super::*;
+
+    fn connector_factory() -> KeyedGrpcConnectorFactory {
+        KeyedGrpcConnectorFactory::new(GrpcConnectorBuilder::new(), |GrpcRoutingKey(key)| {
+            Ok(crate::UnixSocketRoute {
+                socket_path: format!("/nonexistent/{key}.sock").into(),
+                guest_port: None,
+            })
+        })
+    }
+
+    fn cached_keys(channel: &KeyedGrpcChannel) -> usize {
+        channel.shared.channels.lock().unwrap().channels.len()
+    }
+
+    #[test]
+    fn rejects_zero_idle_timeout() {
+        let err = KeyedGrpcChannelBuilder::new(SingletonGrpcChannelBuilder::new(1))
+            .idle_timeout(Duration::ZERO)
+            .try_build(connector_factory())
+            .unwrap_err();
+        assert_eq!(err.field, "idle_timeout");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evicts_idle_channels_without_further_requests() {
+        let channel = KeyedGrpcChannelBuilder::new(SingletonGrpcChannelBuilder::new(1))
+            .idle_timeout(Duration::from_secs(10))
+            .build(connector_factory());
+
+        channel.shared.channel(&"a".into()).unwrap();
+        tokio::time::sleep(Duration::from_secs(6)).await;
+        channel.shared.channel(&"b".into()).unwrap();
+        assert_eq!(cached_keys(&channel), 2);
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert_eq!(cached_keys(&channel), 1);
+        assert!(!channel.evict(&"a".into()));
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(cached_keys(&channel), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn eviction_stops_once_channel_is_dropped() {
+        let channel = KeyedGrpcChannelBuilder::new(SingletonGrpcChannelBuilder::new(1))
+            .idle_timeout(Duration::from_secs(10))
+            .build(connector_factory());
+        channel.shared.channel(&"a".into()).unwrap();
+
+        let shared = Arc::downgrade(&channel.shared);
+        drop(channel);
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(shared.strong_count(), 0);
+    }
+}
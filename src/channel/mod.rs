@@ -1,26 +1,82 @@
+#[cfg(any(feature = "singleton-channel", feature = "pooled-channel"))]
+mod connection;
+mod http2;
+#[cfg(feature = "keyed-channel")]
+mod keyed;
+#[cfg(feature = "pooled-channel")]
+mod pool;
 #[cfg(feature = "pooled-channel")]
 mod pooled;
+mod retry;
 #[cfg(feature = "singleton-channel")]
 mod singleton;
+#[cfg(any(feature = "singleton-channel", feature = "pooled-channel"))]
+mod stats;
+mod unified;
+
+use std::sync::Arc;
 
-use http::{Request, Uri};
+use http::{Request, Response, Uri};
+pub use http2::Http2Config;
+#[cfg(feature = "keyed-channel")]
+pub use keyed::{KeyedGrpcChannel, KeyedGrpcChannelBuilder};
 #[cfg(feature = "pooled-channel")]
 pub use pooled::{PooledGrpcChannel, PooledGrpcChannelBuilder};
 #[cfg(feature = "singleton-channel")]
 pub use singleton::{SingletonGrpcChannel, SingletonGrpcChannelBuilder};
+#[cfg(any(feature = "singleton-channel", feature = "pooled-channel"))]
+pub use stats::{ChannelStats, ConnectionStats, RecordedError};
 use tonic::body::Body;
+use tower::{BoxError, Layer, Service, ServiceExt, util::BoxCloneSyncService};
+pub use unified::{GrpcChannel, GrpcChannelBuilder, GrpcChannelKind};
+
+/// The type-erased [Service] that user-provided tower layers of a channel are applied to.
+type BoxGrpcService = BoxCloneSyncService<Request<Body>, Response<Body>, BoxError>;
+
+type BoxLayerFn = dyn Fn(BoxGrpcService) -> BoxGrpcService + Send + Sync;
+
+/// A stack of type-erased tower [Layer]s, applied in the order of [tower::ServiceBuilder], so that the layer that was
+/// pushed first is the outermost one.
+#[derive(Clone, Default)]
+struct LayerStack {
+    layers: Vec<Arc<BoxLayerFn>>,
+}
+
+impl std::fmt::Debug for LayerStack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LayerStack").field("len", &self.layers.len()).finish()
+    }
+}
+
+impl LayerStack {
+    fn push<L>(&mut self, layer: L)
+    where
+        L: Layer<BoxGrpcService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.layers.push(Arc::new(move |service| {
+            BoxCloneSyncService::new(layer.layer(service).map_err(Into::into))
+        }));
+    }
+
+    fn apply(&self, service: BoxGrpcService) -> BoxGrpcService {
+        self.layers.iter().rev().fold(service, |service, layer| layer(service))
+    }
+}
+
+fn set_request_uri_scheme_and_authority(request: &mut Request<Body>) -> Result<(), BoxError> {
+    let path_and_query = request
+        .uri()
+        .path_and_query()
+        .ok_or("No path and query were specified for a gRPC request")?
+        .clone();
 
-fn set_request_uri_scheme_and_authority(request: &mut Request<Body>) {
     *request.uri_mut() = Uri::builder()
         .scheme("http")
         .authority("localhost")
-        .path_and_query(
-            request
-                .uri()
-                .path_and_query()
-                .expect("No path and query were specified for a gRPC request")
-                .clone(),
-        )
-        .build()
-        .expect("Uri builder failed");
+        .path_and_query(path_and_query)
+        .build()?;
+    Ok(())
 }
@@ -0,0 +1,386 @@
+use std::{
+    sync::{Arc, Mutex, MutexGuard, Weak},
+    time::Duration,
+};
+
+use http::Uri;
+use hyper::client::conn::http2::SendRequest;
+use tokio::sync::Notify;
+use tonic::body::Body;
+use tower::{BoxError, Service, ServiceExt};
+
+use crate::{
+    ChannelStats, ConnectionInfo, GrpcConnector,
+    channel::{
+        connection::{ConnectionActivity, Http2ConnectionBuilder, MaxConnectionAge, PendingRequest, TrackedIo},
+        stats::ChannelMetrics,
+    },
+};
+
+/// The period between maintenances of a [ConnectionPool], which close unusable and idle connections.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The delay before a connection slot of a [ConnectionPool] retries opening a connection after its first failed attempt,
+/// which doubles after every further failed attempt.
+const INITIAL_CONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The longest delay before a connection slot of a [ConnectionPool] retries opening a connection.
+const MAX_CONNECT_BACKOFF: Duration = Duration::from_secs(120);
+
+/// The options of a [ConnectionPool], validated by the builder of the pooled channel.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PoolSettings {
+    pub(crate) streams_per_connection: Option<usize>,
+    pub(crate) min_connections: usize,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) idle_timeout: Duration,
+    pub(crate) max_idle_connections: Option<usize>,
+    pub(crate) max_connection_age: Option<MaxConnectionAge>,
+}
+
+/// A pool of HTTP/2 connections made via a [GrpcConnector], routing every request to the least-loaded connection and
+/// opening more connections whenever all of them carry at least the target number of streams. This struct is cheaply
+/// [Clone]-able, with all clones sharing the same connections.
+#[derive(Clone)]
+pub(crate) struct ConnectionPool {
+    shared: Arc<PoolShared>,
+}
+
+impl std::fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("settings", &self.shared.settings)
+            .finish_non_exhaustive()
+    }
+}
+
+struct PoolShared {
+    connector: GrpcConnector,
+    connection_builder: Http2ConnectionBuilder,
+    settings: PoolSettings,
+    state: Mutex<PoolState>,
+    connection_added: Notify,
+    metrics: Arc<ChannelMetrics>,
+}
+
+#[derive(Default)]
+struct PoolState {
+    connections: Vec<PooledConnection>,
+    connecting: usize,
+    backing_off: usize,
+    last_connect_error: Option<ConnectError>,
+    waiting: usize,
+    replacements: usize,
+    maintenance_started: bool,
+}
+
+/// A connection checked out of a [ConnectionPool] for sending a single request, which counts towards the load of the
+/// connection until its response body is finished.
+#[derive(Clone)]
+pub(crate) struct PooledConnection {
+    pub(crate) send_request: SendRequest<Body>,
+    pub(crate) connection_info: ConnectionInfo,
+    pub(crate) activity: Arc<ConnectionActivity>,
+}
+
+impl PooledConnection {
+    fn is_usable(&self, max_connection_age: Option<MaxConnectionAge>) -> bool {
+        !self.send_request.is_closed()
+            && !self.activity.is_draining()
+            && max_connection_age
+                .is_none_or(|max_connection_age| self.activity.created_at().elapsed() < max_connection_age.age)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.activity.pending_requests() == 0
+    }
+}
+
+impl PoolState {
+    fn open_connections(&self) -> usize {
+        self.connections.len() + self.connecting
+    }
+
+    /// Whether a connection slot that failed to open a connection should keep retrying, which is the case while
+    /// requests are waiting for a connection, the pool has no connections, or the slot is needed for keeping the
+    /// minimum number of connections open.
+    fn needs_connection(&self, settings: &PoolSettings) -> bool {
+        self.waiting > 0 || self.connections.is_empty() || self.open_connections() <= settings.min_connections
+    }
+
+    /// Remove all connections that can no longer be used for new requests, which closes them once their in-flight
+    /// requests finish.
+    fn remove_unusable(&mut self, settings: &PoolSettings) {
+        let len = self.connections.len();
+        self.connections
+            .retain(|connection| connection.is_usable(settings.max_connection_age));
+        self.replacements += len - self.connections.len();
+    }
+
+    /// Remove idle connections that exceed the idle timeout or the maximum number of idle connections, while keeping
+    /// the minimum number of connections open.
+    fn remove_idle(&mut self, settings: &PoolSettings) {
+        let mut idle_connections = self
+            .connections
+            .iter()
+            .filter(|connection| connection.is_idle())
+            .count();
+        let mut index = 0;
+
+        while index < self.connections.len() && self.connections.len() > settings.min_connections {
+            let connection = &self.connections[index];
+            let expired = connection.activity.last_active().elapsed() >= settings.idle_timeout;
+            let excess = settings.max_idle_connections.is_some_and(|max| idle_connections > max);
+
+            if connection.is_idle() && (expired || excess) {
+                self.connections.swap_remove(index);
+                idle_connections -= 1;
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+impl ConnectionPool {
+    pub(crate) fn new(
+        connector: GrpcConnector,
+        connection_builder: Http2ConnectionBuilder,
+        settings: PoolSettings,
+    ) -> Self {
+        Self {
+            shared: Arc::new(PoolShared {
+                connector,
+                connection_builder,
+                settings,
+                state: Mutex::new(PoolState::default()),
+                connection_added: Notify::new(),
+                metrics: Arc::new(ChannelMetrics::default()),
+            }),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> ChannelStats {
+        let waiting = self.shared.lock_state().waiting;
+        self.shared.metrics.snapshot(waiting)
+    }
+
+    /// Check out the least-loaded usable connection. If all connections carry at least the target number of streams,
+    /// wait for a new connection to be opened instead, unless the maximum number of connections is reached or opening a
+    /// connection failed, in which case the least-loaded connection is used regardless of its load. While opening a
+    /// connection fails and the pool has no connections, the error of the last attempt is returned.
+    pub(crate) async fn checkout(&self) -> Result<(PooledConnection, PendingRequest), BoxError> {
+        let settings = &self.shared.settings;
+        let target = settings.streams_per_connection.unwrap_or(usize::MAX);
+
+        loop {
+            let connection_added = self.shared.connection_added.notified();
+            let mut connection_added = std::pin::pin!(connection_added);
+            connection_added.as_mut().enable();
+
+            {
+                let mut state = self.shared.lock_state();
+
+                if !state.maintenance_started {
+                    state.maintenance_started = true;
+                    tokio::task::spawn(maintain(Arc::downgrade(&self.shared)));
+                }
+
+                state.remove_unusable(settings);
+                self.shared.open_min_connections(&mut state);
+
+                let connect_failed = state.backing_off > 0;
+                let can_connect = settings
+                    .max_connections
+                    .is_none_or(|max| state.open_connections() < max);
+                let least_loaded = state
+                    .connections
+                    .iter()
+                    .min_by_key(|connection| connection.activity.pending_requests());
+
+                if let Some(connection) = least_loaded {
+                    if connection.activity.pending_requests() < target || !can_connect || connect_failed {
+                        let pending_request = connection.activity.pending_request();
+                        return Ok((connection.clone(), pending_request));
+                    }
+                } else if let Some(err) = state.last_connect_error.as_ref().filter(|_| connect_failed) {
+                    return Err(Box::new(err.clone()));
+                }
+
+                // Open enough connections for all waiting requests to stay within the target, with one connection
+                // being opened by each waiting request at most
+                state.waiting += 1;
+                if can_connect && state.connecting < state.waiting.div_ceil(target) {
+                    self.shared.connect(&mut state);
+                }
+            }
+
+            let _waiting = WaitingRequest { shared: &self.shared };
+            connection_added.await;
+        }
+    }
+}
+
+/// A guard counting a request as waiting for a connection to be opened, until it is dropped.
+struct WaitingRequest<'a> {
+    shared: &'a PoolShared,
+}
+
+impl Drop for WaitingRequest<'_> {
+    fn drop(&mut self) {
+        self.shared.lock_state().waiting -= 1;
+    }
+}
+
+/// The error of the last failed attempt to open a connection of a [ConnectionPool], which is returned to the requests
+/// checking out a connection while the pool has none and its connection slots are backing off.
+#[derive(Debug, Clone)]
+struct ConnectError(Arc<dyn std::error::Error + Send + Sync>);
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+impl PoolShared {
+    fn lock_state(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().expect("connection pool mutex was poisoned")
+    }
+
+    /// Open new connections in the background until the minimum number of connections is reached.
+    fn open_min_connections(self: &Arc<Self>, state: &mut PoolState) {
+        while state.open_connections() < self.settings.min_connections {
+            self.connect(state);
+        }
+    }
+
+    /// Open a new connection in a connection slot on a background task, so that it is added to the pool even if the
+    /// request waiting for it is cancelled. Requests waiting for a connection are notified of every attempt, and after a
+    /// failed attempt, the slot backs off exponentially before retrying for as long as the pool needs a connection.
+    fn connect(self: &Arc<Self>, state: &mut PoolState) {
+        let pool = Arc::downgrade(self);
+        let reconnect = state.replacements > 0;
+        state.replacements = state.replacements.saturating_sub(1);
+        state.connecting += 1;
+
+        tokio::task::spawn(async move {
+            let mut backoff = INITIAL_CONNECT_BACKOFF;
+
+            loop {
+                let Some(shared) = pool.upgrade() else {
+                    return;
+                };
+
+                let result = shared.open_connection().await;
+                if !shared.finish_connect(result, reconnect) {
+                    return;
+                }
+
+                // The slot doesn't keep the pool alive while backing off
+                drop(shared);
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_CONNECT_BACKOFF);
+
+                if !pool.upgrade().is_some_and(|shared| shared.finish_backoff()) {
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Record the outcome of an attempt to open a connection in a connection slot and notify the requests waiting for
+    /// a connection, returning whether the slot backs off to retry opening a connection.
+    fn finish_connect(&self, result: Result<PooledConnection, BoxError>, reconnect: bool) -> bool {
+        self.metrics.record_connect(result.as_ref().map(|_| ()), reconnect);
+        let mut state = self.lock_state();
+
+        let retry = match result {
+            Ok(connection) => {
+                state.connections.push(connection);
+                false
+            }
+            Err(err) => {
+                state.last_connect_error = Some(ConnectError(Arc::from(err)));
+                state.needs_connection(&self.settings)
+            }
+        };
+
+        match retry {
+            true => state.backing_off += 1,
+            false => state.connecting -= 1,
+        }
+
+        drop(state);
+        self.connection_added.notify_waiters();
+        retry
+    }
+
+    /// End the backoff of a connection slot, returning whether the slot retries opening a connection, or is released
+    /// because the pool no longer needs a connection.
+    fn finish_backoff(&self) -> bool {
+        let mut state = self.lock_state();
+        state.backing_off -= 1;
+
+        let retry = state.needs_connection(&self.settings);
+        if !retry {
+            state.connecting -= 1;
+        }
+
+        retry
+    }
+
+    async fn open_connection(&self) -> Result<PooledConnection, BoxError> {
+        let mut connector = self.connector.clone();
+        let stream = connector
+            .ready()
+            .await?
+            .call(Uri::from_static("http://localhost"))
+            .await?;
+        let connection_info = stream.connection_info().clone();
+        let activity = ConnectionActivity::new(self.metrics.clone());
+        let (send_request, connection) = self
+            .connection_builder
+            .handshake(TrackedIo::new(
+                stream,
+                activity.clone(),
+                self.settings.max_connection_age,
+            ))
+            .await?;
+
+        let connection_activity = activity.clone();
+        tokio::task::spawn(async move { connection_activity.close(connection.await) });
+
+        Ok(PooledConnection {
+            send_request,
+            connection_info,
+            activity,
+        })
+    }
+}
+
+/// Periodically remove unusable and idle connections from the pool and replenish its minimum number of connections,
+/// until the pool is dropped.
+async fn maintain(shared: Weak<PoolShared>) {
+    let mut interval =
+        tokio::time::interval_at(tokio::time::Instant::now() + MAINTENANCE_INTERVAL, MAINTENANCE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+
+        let mut state = shared.lock_state();
+        state.remove_unusable(&shared.settings);
+        state.remove_idle(&shared.settings);
+        shared.open_min_connections(&mut state);
+    }
+}
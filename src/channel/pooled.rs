@@ -4,21 +4,43 @@ use std::{
 };
 
 use http::{Request, Response};
-use hyper::body::Incoming;
-use hyper_util::{
-    client::legacy::{Builder, Client},
-    rt::{TokioExecutor, TokioTimer},
-};
+use hyper_util::rt::{TokioExecutor, TokioTimer};
 use tonic::body::Body;
-use tower::{BoxError, Service};
+use tower::{BoxError, Layer, Service, util::BoxCloneSyncService};
+
+use crate::{
+    BoxResultFuture, ChannelStats, ConfigError, GrpcConnector, Http2Config,
+    channel::{
+        BoxGrpcService, LayerStack,
+        connection::{Http2ConnectionBuilder, MaxConnectionAge},
+        pool::{ConnectionPool, PoolSettings},
+        retry::{TransparentRetry, map_send_error},
+        set_request_uri_scheme_and_authority,
+    },
+};
 
-use crate::{BoxResultFuture, GrpcConnector, channel::set_request_uri_scheme_and_authority};
+/// The default timeout after which idle connections are closed, matching that of [hyper_util]'s legacy client.
+const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
 
 /// A builder for a [PooledGrpcChannel].
 #[derive(Debug, Clone)]
 pub struct PooledGrpcChannelBuilder {
     timeout: Option<Duration>,
-    client_builder: Builder,
+    pool_idle_timeout: Duration,
+    max_idle_connections: Option<usize>,
+    streams_per_connection: Option<usize>,
+    min_connections: usize,
+    max_connections: Option<usize>,
+    http2: Http2Config,
+    max_connection_age: Option<Duration>,
+    max_connection_age_grace: Option<Duration>,
+    layers: LayerStack,
+}
+
+impl Default for PooledGrpcChannelBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PooledGrpcChannelBuilder {
@@ -26,7 +48,15 @@ impl PooledGrpcChannelBuilder {
     pub fn new() -> Self {
         Self {
             timeout: None,
-            client_builder: Builder::new(TokioExecutor::new()),
+            pool_idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+            max_idle_connections: None,
+            streams_per_connection: None,
+            min_connections: 0,
+            max_connections: None,
+            http2: Http2Config::default(),
+            max_connection_age: None,
+            max_connection_age_grace: None,
+            layers: LayerStack::default(),
         }
     }
 
@@ -36,122 +66,450 @@ impl PooledGrpcChannelBuilder {
         self
     }
 
+    /// Set a [Duration] after which connections in the pool that have no requests pending on them are closed, unless
+    /// they are needed for keeping [PooledGrpcChannelBuilder::min_connections] open. Idle connections are closed by the
+    /// maintenance of the pool, which runs every second, so a zero [Duration] closes them within a second. Defaults to
+    /// 90 seconds.
     pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
-        self.client_builder.pool_idle_timeout(timeout);
+        self.pool_idle_timeout = timeout;
         self
     }
 
+    /// Set the maximum number of connections in the pool that have no requests pending on them, beyond which idle
+    /// connections are closed without waiting for the [PooledGrpcChannelBuilder::pool_idle_timeout].
     pub fn max_idle_connections(mut self, max: usize) -> Self {
-        self.client_builder.pool_max_idle_per_host(max);
+        self.max_idle_connections = Some(max);
+        self
+    }
+
+    /// Set the target number of concurrent streams per connection. Requests are routed to the least-loaded connection,
+    /// and once every connection carries at least this many streams, a new connection is opened, up to
+    /// [PooledGrpcChannelBuilder::max_connections]. A stream counts towards the load of its connection until its
+    /// response body is finished or dropped. Without a target, all requests share a single connection, with HTTP/2 queueing
+    /// streams beyond the server's `SETTINGS_MAX_CONCURRENT_STREAMS`.
+    pub fn streams_per_connection(mut self, target: usize) -> Self {
+        self.streams_per_connection = Some(target);
+        self
+    }
+
+    /// Set the minimum number of connections kept open by the pool, which are opened in the background once the
+    /// [PooledGrpcChannel] performs its first request and are exempt from being closed when idle. A connection that
+    /// fails to open is retried with an exponential backoff. Defaults to 0.
+    pub fn min_connections(mut self, min: usize) -> Self {
+        self.min_connections = min;
+        self
+    }
+
+    /// Set the maximum number of connections opened by the pool, after which the least-loaded connection is used even
+    /// if it exceeds the [PooledGrpcChannelBuilder::streams_per_connection] target. Unlimited by default.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Set the [Http2Config] of the HTTP/2 connections in the pool, replacing all HTTP/2 options set previously.
+    pub fn http2(mut self, http2: Http2Config) -> Self {
+        self.http2 = http2;
         self
     }
 
     pub fn http2_max_pending_accept_reset_streams(mut self, max: usize) -> Self {
-        self.client_builder.http2_max_pending_accept_reset_streams(max);
+        self.http2.max_pending_accept_reset_streams = Some(max);
         self
     }
 
     pub fn http2_initial_stream_window_size(mut self, size: u32) -> Self {
-        self.client_builder.http2_initial_stream_window_size(size);
+        self.http2.initial_stream_window_size = Some(size);
         self
     }
 
     pub fn http2_initial_connection_window_size(mut self, size: u32) -> Self {
-        self.client_builder.http2_initial_connection_window_size(size);
+        self.http2.initial_connection_window_size = Some(size);
         self
     }
 
     pub fn http2_initial_max_send_streams(mut self, initial: usize) -> Self {
-        self.client_builder.http2_initial_max_send_streams(initial);
+        self.http2.initial_max_send_streams = Some(initial);
         self
     }
 
     pub fn http2_adaptive_window(mut self, enabled: bool) -> Self {
-        self.client_builder.http2_adaptive_window(enabled);
+        self.http2.adaptive_window = Some(enabled);
         self
     }
 
     pub fn http2_max_frame_size(mut self, size: u32) -> Self {
-        self.client_builder.http2_max_frame_size(size);
+        self.http2.max_frame_size = Some(size);
         self
     }
 
     pub fn http2_max_header_list_size(mut self, size: u32) -> Self {
-        self.client_builder.http2_max_header_list_size(size);
+        self.http2.max_header_list_size = Some(size);
         self
     }
 
     pub fn http2_keep_alive_interval(mut self, interval: Duration) -> Self {
-        self.client_builder.http2_keep_alive_interval(interval);
+        self.http2.keep_alive_interval = Some(interval);
         self
     }
 
     pub fn http2_keep_alive_timeout(mut self, timeout: Duration) -> Self {
-        self.client_builder.http2_keep_alive_timeout(timeout);
+        self.http2.keep_alive_timeout = Some(timeout);
         self
     }
 
     pub fn http2_keep_alive_while_idle(mut self, enabled: bool) -> Self {
-        self.client_builder.http2_keep_alive_while_idle(enabled);
+        self.http2.keep_alive_while_idle = Some(enabled);
         self
     }
 
     pub fn http2_max_concurrent_reset_streams(mut self, max: usize) -> Self {
-        self.client_builder.http2_max_concurrent_reset_streams(max);
+        self.http2.max_concurrent_reset_streams = Some(max);
+        self
+    }
+
+    /// Set a maximum age [Duration] of every HTTP/2 connection in the pool, after which the connection is no longer
+    /// used for new requests, so that a replacement connection is opened for them, while in-flight requests on the old
+    /// connection are allowed to finish. This allows connections through L4 load balancers to be periodically
+    /// rebalanced.
+    pub fn max_connection_age(mut self, age: Duration) -> Self {
+        self.max_connection_age = Some(age);
+        self
+    }
+
+    /// Set a grace period [Duration] for in-flight requests on a connection that reached its
+    /// [PooledGrpcChannelBuilder::max_connection_age], after which the connection is forcibly closed. Without a grace
+    /// period, the connection is kept open until all of its in-flight requests finish. Has no effect unless a maximum
+    /// age is set.
+    pub fn max_connection_age_grace(mut self, grace: Duration) -> Self {
+        self.max_connection_age_grace = Some(grace);
+        self
+    }
+
+    /// Add a per-request tower [Layer] that is applied above the connection pool, so that its services are cloned along
+    /// with the [PooledGrpcChannel] and see every request before a connection is chosen for it. Layers are applied in
+    /// the order of [tower::ServiceBuilder], with the layer added first being the outermost one.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxGrpcService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.layers.push(layer);
         self
     }
 
     /// Build a [PooledGrpcChannel] backed by the given [GrpcConnector].
-    pub fn build(mut self, connector: GrpcConnector) -> PooledGrpcChannel {
-        self.client_builder
-            .http2_only(true)
-            .timer(TokioTimer::new())
-            .pool_timer(TokioTimer::new());
+    ///
+    /// # Panics
+    ///
+    /// Panics if any option set on this builder is invalid. Use [PooledGrpcChannelBuilder::try_build] to handle
+    /// invalid options as a [ConfigError] instead.
+    pub fn build(self, connector: GrpcConnector) -> PooledGrpcChannel {
+        match self.try_build(connector) {
+            Ok(channel) => channel,
+            Err(err) => panic!("{err}"),
+        }
+    }
 
-        let client = self.client_builder.build(connector);
+    /// Build a [PooledGrpcChannel] backed by the given [GrpcConnector], emitting a [ConfigError] for the first invalid
+    /// option that was set on this builder.
+    pub fn try_build(self, connector: GrpcConnector) -> Result<PooledGrpcChannel, ConfigError> {
+        self.validate()?;
+        let max_connection_age = MaxConnectionAge::new(self.max_connection_age, self.max_connection_age_grace)?;
 
-        PooledGrpcChannel {
-            client,
+        let mut connection_builder = Http2ConnectionBuilder::new(TokioExecutor::new());
+        self.http2.apply_to_connection_builder(&mut connection_builder);
+        connection_builder.timer(TokioTimer::new());
+
+        let pool = ConnectionPool::new(
+            connector,
+            connection_builder,
+            PoolSettings {
+                streams_per_connection: self.streams_per_connection,
+                min_connections: self.min_connections,
+                max_connections: self.max_connections,
+                idle_timeout: self.pool_idle_timeout,
+                max_idle_connections: self.max_idle_connections,
+                max_connection_age,
+            },
+        );
+
+        let service = BoxCloneSyncService::new(PooledService {
+            pool: pool.clone(),
             timeout: self.timeout,
+        });
+
+        Ok(PooledGrpcChannel {
+            service: self
+                .layers
+                .apply(BoxCloneSyncService::new(TransparentRetry::new(service))),
+            pool,
+        })
+    }
+
+    /// Emit a [ConfigError] for the first invalid option that was set on this builder.
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if self.streams_per_connection == Some(0) {
+            return Err(ConfigError::new("streams_per_connection", "must be greater than 0"));
+        }
+
+        if let Some(max) = self.max_connections {
+            if max == 0 {
+                return Err(ConfigError::new("max_connections", "must be greater than 0"));
+            }
+
+            if self.min_connections > max {
+                return Err(ConfigError::new("min_connections", "must not exceed max_connections"));
+            }
         }
+
+        MaxConnectionAge::new(self.max_connection_age, self.max_connection_age_grace)?;
+        self.http2.validate()
     }
 }
 
 /// A gRPC channel [Service] compatible with [tonic] that is backed by a dynamic HTTP/2 connection
-/// pool. Currently, [hyper_util]'s legacy client is used as this pool, but this may change in the future while
-/// preserving the backwards compatibility and functionality of [PooledGrpcChannel]. To use this channel with
-/// [tonic] for performing requests, create a [tonic::client::Grpc] instance wrapping it or a code-generated client
-/// struct wrapping it.
+/// pool, which routes every request to its least-loaded connection and opens more connections under load, as configured
+/// via [PooledGrpcChannelBuilder::streams_per_connection]. To use this channel with [tonic] for performing requests,
+/// create a [tonic::client::Grpc] instance wrapping it or a code-generated client struct wrapping it.
 #[derive(Debug, Clone)]
 pub struct PooledGrpcChannel {
-    client: Client<GrpcConnector, Body>,
-    timeout: Option<Duration>,
+    service: BoxGrpcService,
+    pool: ConnectionPool,
+}
+
+impl PooledGrpcChannel {
+    /// Take a snapshot of the [ChannelStats] of this [PooledGrpcChannel], which are shared by all of its clones.
+    pub fn stats(&self) -> ChannelStats {
+        self.pool.stats()
+    }
 }
 
 impl Service<Request<Body>> for PooledGrpcChannel {
-    type Response = Response<Incoming>;
+    type Response = Response<Body>;
 
     type Error = BoxError;
 
-    type Future = BoxResultFuture<Response<Incoming>>;
+    type Future = BoxResultFuture<Response<Body>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.client.poll_ready(cx).map_err(|err| Box::new(err) as BoxError)
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        self.service.call(request)
+    }
+}
+
+#[derive(Clone)]
+struct PooledService {
+    pool: ConnectionPool,
+    timeout: Option<Duration>,
+}
+
+impl Service<Request<Body>> for PooledService {
+    type Response = Response<Body>;
+
+    type Error = BoxError;
+
+    type Future = BoxResultFuture<Response<Body>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
     }
 
     fn call(&mut self, mut request: Request<Body>) -> Self::Future {
-        set_request_uri_scheme_and_authority(&mut request);
-        let future = self.client.request(request);
+        if let Err(err) = set_request_uri_scheme_and_authority(&mut request) {
+            return Box::pin(std::future::ready(Err(err)));
+        }
+
+        let pool = self.pool.clone();
+
+        let future = async move {
+            let (mut connection, pending_request) = pool.checkout().await?;
+            let mut response = connection
+                .send_request
+                .try_send_request(request)
+                .await
+                .map_err(|err| map_send_error(err, &connection.activity))?;
+            response.extensions_mut().insert(connection.connection_info);
+            Ok(pending_request.hold_until_end(response))
+        };
 
         match self.timeout {
             Some(timeout) => Box::pin(async move {
                 match tokio::time::timeout(timeout, future).await {
-                    Ok(Ok(response)) => Ok(response),
-                    Ok(Err(err)) => Err(Box::new(err) as BoxError),
+                    Ok(result) => result,
                     Err(err) => Err(Box::new(err) as BoxError),
                 }
             }),
-            None => Box::pin(async { future.await.map_err(|err| Box::new(err) as BoxError) }),
+            None => Box::pin(future),
         }
     }
 }
+
+#[cfg(all(test, feature = "unix-transport"))]
+mod tests {
+    use std::path::Path;
+
+    use http_body_util::BodyExt;
+    use tokio::{net::UnixListener, sync::watch};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::GrpcConnectorBuilder;
+
+    /// Spawn an HTTP/2 server on a Unix socket at the given path, answering every request with response headers right
+    /// away, and with the trailers ending the response once the returned sender is set to `true`.
+    fn spawn_server(socket_path: &Path) -> watch::Sender<bool> {
+        let listener = UnixListener::bind(socket_path).unwrap();
+        let (release, released) = watch::channel(false);
+
+        tokio::spawn(async move {
+            while let Ok((io, _)) = listener.accept().await {
+                let released = released.clone();
+
+                tokio::spawn(async move {
+                    let mut connection = h2::server::handshake(io).await.unwrap();
+
+                    while let Some(Ok((_, mut respond))) = connection.accept().await {
+                        let mut released = released.clone();
+
+                        tokio::spawn(async move {
+                            let response = Response::builder()
+                                .header("content-type", "application/grpc")
+                                .body(())
+                                .unwrap();
+                            let mut stream = respond.send_response(response, false).unwrap();
+                            let _ = released.wait_for(|released| *released).await;
+
+                            let mut trailers = http::HeaderMap::new();
+                            trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+                            let _ = stream.send_trailers(trailers);
+                        });
+                    }
+                });
+            }
+        });
+
+        release
+    }
+
+    async fn send(channel: &PooledGrpcChannel) -> Result<Response<Body>, BoxError> {
+        let request = Request::builder()
+            .uri("http://localhost/test.Service/Method")
+            .body(Body::empty())
+            .unwrap();
+        channel.clone().oneshot(request).await
+    }
+
+    async fn wait_until<F: Fn(&ChannelStats) -> bool>(channel: &PooledGrpcChannel, condition: F) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !condition(&channel.stats()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    fn in_flight_streams(stats: &ChannelStats) -> Vec<usize> {
+        let mut in_flight_streams: Vec<_> = stats
+            .connections
+            .iter()
+            .map(|connection| connection.in_flight_streams)
+            .collect();
+        in_flight_streams.sort();
+        in_flight_streams
+    }
+
+    #[tokio::test]
+    async fn opens_connections_for_streaming_responses_under_load() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("grpc.sock");
+        let release = spawn_server(&socket_path);
+
+        let channel = PooledGrpcChannelBuilder::new()
+            .streams_per_connection(2)
+            .build(GrpcConnectorBuilder::new().build_to_unix_socket(&socket_path));
+
+        // Every response is streaming after its headers were received, so it keeps counting towards the load
+        let mut responses = Vec::new();
+        for _ in 0..5 {
+            responses.push(send(&channel).await.unwrap());
+        }
+        assert_eq!(in_flight_streams(&channel.stats()), [1, 2, 2]);
+
+        release.send(true).unwrap();
+        for response in responses {
+            response.into_body().collect().await.unwrap();
+        }
+        assert_eq!(in_flight_streams(&channel.stats()), [0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn opens_min_connections_in_background() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("grpc.sock");
+        spawn_server(&socket_path).send(true).unwrap();
+
+        let channel = PooledGrpcChannelBuilder::new()
+            .min_connections(3)
+            .build(GrpcConnectorBuilder::new().build_to_unix_socket(&socket_path));
+        send(&channel).await.unwrap().into_body().collect().await.unwrap();
+
+        wait_until(&channel, |stats| stats.connections.len() == 3).await;
+        assert_eq!(channel.stats().total_connects, 3);
+    }
+
+    #[tokio::test]
+    async fn closes_idle_connections_down_to_min_connections() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("grpc.sock");
+        let release = spawn_server(&socket_path);
+
+        let channel = PooledGrpcChannelBuilder::new()
+            .streams_per_connection(1)
+            .min_connections(1)
+            .pool_idle_timeout(Duration::ZERO)
+            .build(GrpcConnectorBuilder::new().build_to_unix_socket(&socket_path));
+
+        let mut responses = Vec::new();
+        for _ in 0..3 {
+            responses.push(send(&channel).await.unwrap());
+        }
+        assert_eq!(in_flight_streams(&channel.stats()), [1, 1, 1]);
+
+        release.send(true).unwrap();
+        for response in responses {
+            response.into_body().collect().await.unwrap();
+        }
+
+        wait_until(&channel, |stats| stats.connections.len() == 1).await;
+        assert!(channel.stats().last_error.is_none());
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_failing_to_connect() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("grpc.sock");
+
+        let channel =
+            PooledGrpcChannelBuilder::new().build(GrpcConnectorBuilder::new().build_to_unix_socket(&socket_path));
+        send(&channel).await.unwrap_err();
+
+        // Further requests fail with the last error while the connection slot is backing off, without connecting
+        let err = send(&channel).await.unwrap_err();
+        let stats = channel.stats();
+        assert_eq!(stats.connect_failures, 1);
+        assert_eq!(stats.last_error.unwrap().message, err.to_string());
+
+        // The connection slot keeps retrying while the pool has no connections
+        spawn_server(&socket_path).send(true).unwrap();
+        wait_until(&channel, |stats| stats.total_connects == 1).await;
+        send(&channel).await.unwrap().into_body().collect().await.unwrap();
+        assert_eq!(channel.stats().connect_failures, 1);
+    }
+}
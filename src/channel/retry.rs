@@ -0,0 +1,356 @@
+use std::{
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use http::{Request, Response};
+use hyper::client::conn::TrySendError;
+use tonic::body::Body;
+use tower::{BoxError, Service, ServiceExt};
+
+use crate::{
+    BoxResultFuture,
+    channel::{BoxGrpcService, connection::ConnectionActivity},
+    replay,
+};
+
+/// The maximum size of a request body that is recorded so that the request can be transparently retried after the
+/// server reported it as unprocessed.
+const MAX_REPLAY_SIZE: usize = 64 * 1024;
+
+/// An error returned for a request that was never sent over a connection, because the connection was closing or closed
+/// before the request could be written to it. The request is carried along so that it can be retried as-is.
+#[derive(Debug)]
+pub(crate) struct UnsentRequest {
+    request: Mutex<Option<Request<Body>>>,
+    error: BoxError,
+}
+
+impl UnsentRequest {
+    pub(crate) fn new<E: Into<BoxError>>(request: Request<Body>, error: E) -> Self {
+        Self {
+            request: Mutex::new(Some(request)),
+            error: error.into(),
+        }
+    }
+
+    fn take_request(&self) -> Option<Request<Body>> {
+        self.request.lock().expect("unsent request mutex was poisoned").take()
+    }
+}
+
+impl std::fmt::Display for UnsentRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The request was not sent: {}", self.error)
+    }
+}
+
+impl std::error::Error for UnsentRequest {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+/// Find the HTTP/2 error within the source chain of the given error, if any.
+fn find_h2_error<'a>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a h2::Error> {
+    let mut source = Some(err);
+
+    while let Some(err) = source {
+        if let Some(h2_error) = err.downcast_ref::<h2::Error>() {
+            return Some(h2_error);
+        }
+
+        source = err.source();
+    }
+
+    None
+}
+
+/// Whether the given error was caused by a GOAWAY frame sent by the server.
+fn is_remote_go_away(err: &(dyn std::error::Error + 'static)) -> bool {
+    find_h2_error(err).is_some_and(|h2_error| h2_error.is_go_away() && h2_error.is_remote())
+}
+
+/// Whether the server reported the request as unprocessed, being a stream reset with REFUSED_STREAM, or a GOAWAY frame
+/// with NO_ERROR, which h2 only reports for streams above the last stream ID of the GOAWAY frame and streams opened
+/// after it. A GOAWAY frame carrying an error code is reported for all streams of the connection, including processed
+/// ones, so it doesn't mark a request as unprocessed. Unprocessed requests can safely be retried on another connection.
+fn is_unprocessed(err: &(dyn std::error::Error + 'static)) -> bool {
+    find_h2_error(err).is_some_and(|h2_error| {
+        h2_error.is_remote()
+            && match h2_error.reason() {
+                Some(h2::Reason::NO_ERROR) => h2_error.is_go_away(),
+                Some(h2::Reason::REFUSED_STREAM) => h2_error.is_reset(),
+                _ => false,
+            }
+    })
+}
+
+/// Convert the error of sending a request over a connection into a [BoxError], marking the connection as draining when
+/// the request was never sent or was rejected by a GOAWAY frame, as either means that the connection is going away.
+/// A request that was never sent is carried along as an [UnsentRequest], so that [TransparentRetry] can retry it. The
+/// error is recorded as the last error of the channel.
+pub(crate) fn map_send_error(mut err: TrySendError<Request<Body>>, activity: &ConnectionActivity) -> BoxError {
+    activity.metrics().record_error(err.error());
+
+    match err.take_message() {
+        Some(request) => {
+            activity.drain();
+            Box::new(UnsentRequest::new(request, err.into_error()))
+        }
+        None => {
+            let err = err.into_error();
+
+            if is_remote_go_away(&err) {
+                activity.drain();
+            }
+
+            Box::new(err)
+        }
+    }
+}
+
+/// A [Service] transparently retrying a request once when it was either never sent or reported by the server as
+/// unprocessed, like gRPC-core's transparent retry. A retried request is sent through the same inner service, which
+/// routes it to a new connection when the previous one is going away. Requests whose body was not sent completely or
+/// exceeded [MAX_REPLAY_SIZE] are only retried if they were never sent.
+#[derive(Clone)]
+pub(crate) struct TransparentRetry {
+    inner: BoxGrpcService,
+}
+
+impl TransparentRetry {
+    pub(crate) fn new(inner: BoxGrpcService) -> Self {
+        Self { inner }
+    }
+}
+
+impl Service<Request<Body>> for TransparentRetry {
+    type Response = Response<Body>;
+
+    type Error = BoxError;
+
+    type Future = BoxResultFuture<Response<Body>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (request, replay) = replay::record(request, MAX_REPLAY_SIZE);
+
+            let err = match inner.call(request).await {
+                Ok(response) => return Ok(response),
+                Err(err) => err,
+            };
+
+            let request = match err.downcast_ref::<UnsentRequest>() {
+                Some(unsent_request) => unsent_request.take_request(),
+                None if is_unprocessed(err.as_ref()) => replay.replay(),
+                None => None,
+            };
+
+            match request {
+                Some(request) => inner.ready().await?.call(request).await,
+                None => Err(err),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use tower::util::BoxCloneSyncService;
+
+    use super::*;
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .uri("http://localhost/test.Service/Method")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    /// A service failing the first request with the error produced by the given function and answering all others,
+    /// along with the number of requests it received.
+    fn failing_once<F>(error: F) -> (BoxGrpcService, Arc<AtomicUsize>)
+    where
+        F: Fn(Request<Body>) -> BoxError + Clone + Send + Sync + 'static,
+    {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+
+        let service = tower::service_fn(move |request: Request<Body>| {
+            let first = counter.fetch_add(1, Ordering::SeqCst) == 0;
+            let error = error.clone();
+
+            async move {
+                match first {
+                    true => Err(error(request)),
+                    false => Ok(Response::new(Body::empty())),
+                }
+            }
+        });
+
+        (BoxCloneSyncService::new(service), requests)
+    }
+
+    #[tokio::test]
+    async fn replays_unsent_requests() {
+        let (service, requests) = failing_once(|request| Box::new(UnsentRequest::new(request, "connection closed")));
+        TransparentRetry::new(service).oneshot(request()).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_replay_other_errors() {
+        let (service, requests) = failing_once(|_| "connection reset".into());
+        let err = TransparentRetry::new(service).oneshot(request()).await.err().unwrap();
+        assert_eq!(err.to_string(), "connection reset");
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(all(feature = "singleton-channel", feature = "unix-transport"))]
+    mod http2 {
+        use std::{collections::VecDeque, path::Path, sync::Mutex, time::Duration};
+
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::{UnixListener, UnixStream},
+        };
+
+        use super::*;
+        use crate::{GrpcConnectorBuilder, SingletonGrpcChannelBuilder};
+
+        const HEADERS_FRAME: u8 = 0x1;
+        const RST_STREAM_FRAME: u8 = 0x3;
+        const SETTINGS_FRAME: u8 = 0x4;
+        const GOAWAY_FRAME: u8 = 0x7;
+        const END_STREAM_AND_HEADERS: u8 = 0x5;
+        const ACK: u8 = 0x1;
+
+        /// How the server answers a request.
+        #[derive(Debug, Clone, Copy)]
+        enum Action {
+            /// Respond with a `200` status.
+            Respond,
+            /// Reset the stream of the request with the given reason.
+            Reset(h2::Reason),
+            /// Send a GOAWAY frame with the given last stream ID and reason, closing the connection unless the reason
+            /// is NO_ERROR.
+            GoAway(u32, h2::Reason),
+        }
+
+        async fn write_frame(stream: &mut UnixStream, frame_type: u8, flags: u8, stream_id: u32, payload: &[u8]) {
+            let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+            frame.extend_from_slice(&[frame_type, flags]);
+            frame.extend_from_slice(&stream_id.to_be_bytes());
+            frame.extend_from_slice(payload);
+            stream.write_all(&frame).await.unwrap();
+        }
+
+        /// Serve a single HTTP/2 connection by hand, answering the request on every stream with the next [Action].
+        async fn serve_connection(
+            mut stream: UnixStream,
+            actions: Arc<Mutex<VecDeque<Action>>>,
+            requests: Arc<AtomicUsize>,
+        ) {
+            let mut preface = [0; 24];
+            stream.read_exact(&mut preface).await.unwrap();
+            write_frame(&mut stream, SETTINGS_FRAME, 0, 0, &[]).await;
+
+            let mut header = [0; 9];
+            while stream.read_exact(&mut header).await.is_ok() {
+                let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+                let (frame_type, flags) = (header[3], header[4]);
+                let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+                stream.read_exact(&mut vec![0; len]).await.unwrap();
+
+                match frame_type {
+                    SETTINGS_FRAME if flags & ACK == 0 => write_frame(&mut stream, SETTINGS_FRAME, ACK, 0, &[]).await,
+                    HEADERS_FRAME => {
+                        requests.fetch_add(1, Ordering::SeqCst);
+
+                        let action = actions.lock().unwrap().pop_front().unwrap_or(Action::Respond);
+                        match action {
+                            // `:status: 200` is entry 8 of the HPACK static table
+                            Action::Respond => {
+                                write_frame(&mut stream, HEADERS_FRAME, END_STREAM_AND_HEADERS, stream_id, &[0x88])
+                                    .await
+                            }
+                            Action::Reset(reason) => {
+                                let payload = u32::from(reason).to_be_bytes();
+                                write_frame(&mut stream, RST_STREAM_FRAME, 0, stream_id, &payload).await;
+                            }
+                            Action::GoAway(last_stream_id, reason) => {
+                                let mut payload = last_stream_id.to_be_bytes().to_vec();
+                                payload.extend_from_slice(&u32::from(reason).to_be_bytes());
+                                write_frame(&mut stream, GOAWAY_FRAME, 0, 0, &payload).await;
+
+                                if reason != h2::Reason::NO_ERROR {
+                                    return;
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        /// Spawn an HTTP/2 server on a Unix socket at the given path, answering the first request with the given
+        /// [Action] and all others with [Action::Respond], along with the number of requests it received.
+        fn spawn_server(socket_path: &Path, action: Action) -> Arc<AtomicUsize> {
+            let listener = UnixListener::bind(socket_path).unwrap();
+            let actions = Arc::new(Mutex::new(VecDeque::from([action])));
+            let requests = Arc::new(AtomicUsize::new(0));
+            let counter = requests.clone();
+
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve_connection(stream, actions.clone(), counter.clone()));
+                }
+            });
+
+            requests
+        }
+
+        #[tokio::test]
+        async fn replays_only_unprocessed_requests() {
+            let cases = [
+                (Action::Reset(h2::Reason::REFUSED_STREAM), true),
+                (Action::Reset(h2::Reason::CANCEL), false),
+                (Action::GoAway(0, h2::Reason::NO_ERROR), true),
+                (Action::GoAway(0, h2::Reason::INTERNAL_ERROR), false),
+                (Action::GoAway(1, h2::Reason::INTERNAL_ERROR), false),
+            ];
+
+            for (action, replayed) in cases {
+                let directory = tempfile::tempdir().unwrap();
+                let socket_path = directory.path().join("grpc.sock");
+                let requests = spawn_server(&socket_path, action);
+                let channel = SingletonGrpcChannelBuilder::new(16)
+                    .build(GrpcConnectorBuilder::new().build_to_unix_socket(&socket_path));
+
+                let result = tokio::time::timeout(Duration::from_secs(5), channel.oneshot(request()))
+                    .await
+                    .unwrap();
+                assert_eq!(result.is_ok(), replayed, "{action:?}");
+                assert_eq!(
+                    requests.load(Ordering::SeqCst),
+                    if replayed { 2 } else { 1 },
+                    "{action:?}"
+                );
+            }
+        }
+    }
+}
@@ -1,54 +1,102 @@
 use std::{
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
     time::Duration,
 };
 
 use http::{Request, Response};
-use hyper::body::Incoming;
 use hyper_util::rt::{TokioExecutor, TokioTimer};
 use tonic::body::Body;
 use tower::{
-    BoxError, Service, ServiceBuilder, buffer::Buffer, reconnect::Reconnect, timeout::TimeoutLayer,
+    BoxError, Layer, Service, ServiceBuilder, ServiceExt, buffer::Buffer, reconnect::Reconnect, timeout::TimeoutLayer,
     util::BoxCloneSyncService,
 };
 
-use crate::{GrpcConnector, channel::set_request_uri_scheme_and_authority};
-
-type Http2ConnectionBuilder = hyper::client::conn::http2::Builder<TokioExecutor>;
+use crate::{
+    ChannelStats, ConfigError, ConnectionInfo, GrpcConnector, Http2Config,
+    channel::{
+        BoxGrpcService, LayerStack,
+        connection::{
+            ConnectionActivity, Http2ConnectionBuilder, MaxConnectionAge, SharedSendRequest, TrackedIo,
+            drive_connection,
+        },
+        retry::{TransparentRetry, UnsentRequest, map_send_error},
+        set_request_uri_scheme_and_authority,
+        stats::{BufferedRequest, ChannelMetrics},
+    },
+};
 
 #[derive(Clone)]
 struct SingletonService {
-    send_request: hyper::client::conn::http2::SendRequest<Body>,
+    send_request: SharedSendRequest,
+    connection_info: ConnectionInfo,
+    activity: Arc<ConnectionActivity>,
+    max_connection_age: Option<MaxConnectionAge>,
     timeout: Option<Duration>,
 }
 
 impl tower::Service<Request<Body>> for SingletonService {
-    type Response = http::Response<hyper::body::Incoming>;
+    type Response = http::Response<Body>;
 
     type Error = Box<dyn std::error::Error + Send + Sync>;
 
     type Future = std::pin::Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
 
     fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> std::task::Poll<Result<(), Self::Error>> {
-        self.send_request
-            .poll_ready(cx)
-            .map_err(|err| Box::new(err) as Box<dyn std::error::Error + Send + Sync>)
+        // Failing readiness makes the connection get replaced, while its in-flight requests are allowed to finish
+        if self
+            .max_connection_age
+            .is_some_and(|max_connection_age| self.activity.created_at().elapsed() >= max_connection_age.age)
+        {
+            return Poll::Ready(Err("connection reached its maximum age".into()));
+        }
+
+        // Likewise, a connection the server is going away from is replaced before its closure is observed by hyper
+        if self.activity.is_draining() {
+            return Poll::Ready(Err("connection is going away".into()));
+        }
+
+        match *self.send_request.lock().expect("send request mutex was poisoned") {
+            Some(ref mut send_request) => send_request
+                .poll_ready(cx)
+                .map_err(|err| Box::new(err) as Box<dyn std::error::Error + Send + Sync>),
+            None => Poll::Ready(Err("connection was closed after being idle".into())),
+        }
     }
 
     fn call(&mut self, mut request: Request<Body>) -> Self::Future {
-        set_request_uri_scheme_and_authority(&mut request);
-        let future = self.send_request.send_request(request);
+        request.extensions_mut().remove::<BufferedRequest>();
+
+        if let Err(err) = set_request_uri_scheme_and_authority(&mut request) {
+            return Box::pin(std::future::ready(Err(err)));
+        }
+
+        // The connection may have been closed after being idle since this service was found ready
+        let (future, pending_request) = match *self.send_request.lock().expect("send request mutex was poisoned") {
+            Some(ref mut send_request) => (send_request.try_send_request(request), self.activity.pending_request()),
+            None => {
+                let err = UnsentRequest::new(request, "connection was closed after being idle");
+                return Box::pin(std::future::ready(Err(Box::new(err) as BoxError)));
+            }
+        };
+        let connection_info = self.connection_info.clone();
+        let activity = self.activity.clone();
+
+        let future = async move {
+            let mut response = future.await.map_err(|err| map_send_error(err, &activity))?;
+            response.extensions_mut().insert(connection_info);
+            Ok(pending_request.hold_until_end(response))
+        };
 
         match self.timeout {
             Some(timeout) => Box::pin(async move {
                 match tokio::time::timeout(timeout, future).await {
-                    Ok(Ok(response)) => Ok(response),
-                    Ok(Err(err)) => Err(Box::new(err) as BoxError),
+                    Ok(result) => result,
                     Err(err) => Err(Box::new(err) as BoxError),
                 }
             }),
-            None => Box::pin(async move { future.await.map_err(|err| Box::new(err) as BoxError) }),
+            None => Box::pin(future),
         }
     }
 }
@@ -56,11 +104,15 @@ impl tower::Service<Request<Body>> for SingletonService {
 struct SingletonConnectService {
     connector: GrpcConnector,
     connection_builder: Http2ConnectionBuilder,
+    connection_layers: LayerStack,
     timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    max_connection_age: Option<MaxConnectionAge>,
+    metrics: Arc<ChannelMetrics>,
 }
 
 impl tower::Service<()> for SingletonConnectService {
-    type Response = SingletonService;
+    type Response = BoxGrpcService;
 
     type Error = Box<dyn std::error::Error + Send + Sync>;
 
@@ -73,15 +125,46 @@ impl tower::Service<()> for SingletonConnectService {
     fn call(&mut self, _: ()) -> Self::Future {
         let mut connector = self.connector.clone();
         let connection_builder = self.connection_builder.clone();
+        let connection_layers = self.connection_layers.clone();
         let timeout = self.timeout;
+        let idle_timeout = self.idle_timeout;
+        let max_connection_age = self.max_connection_age;
+        let metrics = self.metrics.clone();
 
         Box::pin(async move {
-            let stream = connector.call(http::Uri::from_static("http://localhost")).await?;
-            let (send_request, connection) = connection_builder.handshake(stream).await?;
-
-            tokio::task::spawn(connection);
+            let reconnect = metrics.has_connected();
+            let result = async {
+                let stream = connector.call(http::Uri::from_static("http://localhost")).await?;
+                let connection_info = stream.connection_info().clone();
+                let activity = ConnectionActivity::new(metrics.clone());
+                let (send_request, connection) = connection_builder
+                    .handshake(TrackedIo::new(stream, activity.clone(), max_connection_age))
+                    .await?;
+
+                Ok::<_, BoxError>((send_request, connection, connection_info, activity))
+            }
+            .await;
+
+            metrics.record_connect(result.as_ref().map(|_| ()), reconnect);
+            let (send_request, connection, connection_info, activity) = result?;
+
+            let send_request = Arc::new(std::sync::Mutex::new(Some(send_request)));
+            tokio::task::spawn(drive_connection(
+                connection,
+                send_request.clone(),
+                activity.clone(),
+                idle_timeout,
+            ));
 
-            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(SingletonService { send_request, timeout })
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(connection_layers.apply(BoxCloneSyncService::new(
+                SingletonService {
+                    send_request,
+                    connection_info,
+                    activity,
+                    max_connection_age,
+                    timeout,
+                },
+            )))
         })
     }
 }
@@ -91,7 +174,13 @@ impl tower::Service<()> for SingletonConnectService {
 pub struct SingletonGrpcChannelBuilder {
     buffer_size: usize,
     connection_builder: Http2ConnectionBuilder,
+    http2: Http2Config,
+    layers: LayerStack,
+    connection_layers: LayerStack,
     timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    max_connection_age: Option<Duration>,
+    max_connection_age_grace: Option<Duration>,
 }
 
 impl SingletonGrpcChannelBuilder {
@@ -101,12 +190,26 @@ impl SingletonGrpcChannelBuilder {
         Self {
             buffer_size,
             connection_builder: Http2ConnectionBuilder::new(TokioExecutor::new()),
+            http2: Http2Config::default(),
+            layers: LayerStack::default(),
+            connection_layers: LayerStack::default(),
             timeout: None,
+            idle_timeout: None,
+            max_connection_age: None,
+            max_connection_age_grace: None,
         }
     }
 
+    /// Set the [Http2Config] of the HTTP/2 connection, replacing the one set previously.
+    pub fn http2(mut self, http2: Http2Config) -> Self {
+        self.http2 = http2;
+        self
+    }
+
     /// Set options on the [hyper] HTTP/2 connection builder via a function taking a mutable reference to the
-    /// builder. HTTP/2 connection options can be customized this way via [hyper]'s low-level client API.
+    /// builder. HTTP/2 connection options can be customized this way via [hyper]'s low-level client API. Options set
+    /// in the [Http2Config] take precedence over the ones set here.
+    #[deprecated(note = "use SingletonGrpcChannelBuilder::http2 with an Http2Config instead")]
     pub fn configure_http2_connection<F: FnOnce(&mut Http2ConnectionBuilder)>(mut self, function: F) -> Self {
         function(&mut self.connection_builder);
         self
@@ -118,8 +221,81 @@ impl SingletonGrpcChannelBuilder {
         self
     }
 
-    pub fn build(mut self, connector: GrpcConnector) -> SingletonGrpcChannel {
+    /// Set a [Duration] after which the HTTP/2 connection is gracefully closed with a GOAWAY frame when no requests have
+    /// been pending on it and no data has been transferred over it, moving the [SingletonGrpcChannel] to an idle state, from which the next request
+    /// transparently reconnects. Since long-lived streams may be silent for longer than the idle timeout, setting an
+    /// [Http2Config::keep_alive_interval] is recommended, which keeps connections with open streams active via pings.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Set a maximum age [Duration] of the HTTP/2 connection, after which no new requests are sent over it and the next
+    /// request opens a replacement connection, while in-flight requests on the old connection are allowed to finish.
+    /// This allows connections through L4 load balancers to be periodically rebalanced.
+    pub fn max_connection_age(mut self, age: Duration) -> Self {
+        self.max_connection_age = Some(age);
+        self
+    }
+
+    /// Set a grace period [Duration] for in-flight requests on a connection that reached its
+    /// [SingletonGrpcChannelBuilder::max_connection_age], after which the connection is forcibly closed. Without a grace
+    /// period, the connection is kept open until all of its in-flight requests finish. Has no effect unless a maximum
+    /// age is set.
+    pub fn max_connection_age_grace(mut self, grace: Duration) -> Self {
+        self.max_connection_age_grace = Some(grace);
+        self
+    }
+
+    /// Add a per-request tower [Layer] that is applied above the buffer, so that its services are cloned along with the
+    /// [SingletonGrpcChannel] and see every request before it is sent to the background task, surviving reconnects.
+    /// Layers are applied in the order of [ServiceBuilder], with the layer added first being the outermost one.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxGrpcService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Add a per-connection tower [Layer] that is applied around the service sending requests over each HTTP/2
+    /// connection, so that a new instance of its service is created whenever the channel reconnects. Layers are applied
+    /// in the order of [ServiceBuilder], with the layer added first being the outermost one.
+    pub fn connection_layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxGrpcService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.connection_layers.push(layer);
+        self
+    }
+
+    /// Build a [SingletonGrpcChannel] backed by the given [GrpcConnector].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any option set on this builder is invalid. Use [SingletonGrpcChannelBuilder::try_build] to handle
+    /// invalid options as a [ConfigError] instead.
+    pub fn build(self, connector: GrpcConnector) -> SingletonGrpcChannel {
+        match self.try_build(connector) {
+            Ok(channel) => channel,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Build a [SingletonGrpcChannel] backed by the given [GrpcConnector], emitting a [ConfigError] for the first
+    /// invalid option that was set on this builder.
+    pub fn try_build(mut self, connector: GrpcConnector) -> Result<SingletonGrpcChannel, ConfigError> {
+        self.validate()?;
+        let max_connection_age = MaxConnectionAge::new(self.max_connection_age, self.max_connection_age_grace)?;
+        self.http2.apply_to_connection_builder(&mut self.connection_builder);
         self.connection_builder.timer(TokioTimer::new());
+        let metrics = Arc::new(ChannelMetrics::default());
 
         let service = ServiceBuilder::new()
             .option_layer(self.timeout.map(TimeoutLayer::new))
@@ -127,14 +303,43 @@ impl SingletonGrpcChannelBuilder {
                 SingletonConnectService {
                     connector,
                     connection_builder: self.connection_builder,
+                    connection_layers: self.connection_layers,
                     timeout: self.timeout,
+                    idle_timeout: self.idle_timeout,
+                    max_connection_age,
+                    metrics: metrics.clone(),
                 },
                 (),
             ));
 
-        let buffer = BoxCloneSyncService::new(Buffer::new(service, self.buffer_size));
+        // Requests are counted as buffered until the service of the connection receives them
+        let buffer_metrics = metrics.clone();
+        let buffer = BoxCloneSyncService::new(Buffer::new(service, self.buffer_size).map_request(
+            move |mut request: Request<Body>| {
+                request.extensions_mut().insert(buffer_metrics.buffered_request());
+                request
+            },
+        ));
+        let retry = BoxCloneSyncService::new(TransparentRetry::new(buffer));
+
+        Ok(SingletonGrpcChannel {
+            service: self.layers.apply(retry),
+            metrics,
+        })
+    }
+
+    /// Emit a [ConfigError] for the first invalid option that was set on this builder.
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if self.buffer_size == 0 {
+            return Err(ConfigError::new("buffer_size", "must be greater than 0"));
+        }
 
-        SingletonGrpcChannel { buffer }
+        if self.idle_timeout.is_some_and(|timeout| timeout.is_zero()) {
+            return Err(ConfigError::new("idle_timeout", "must be greater than 0"));
+        }
+
+        MaxConnectionAge::new(self.max_connection_age, self.max_connection_age_grace)?;
+        self.http2.validate()
     }
 }
 
@@ -145,21 +350,209 @@ impl SingletonGrpcChannelBuilder {
 /// [tonic::client::Grpc] instance wrapping it or a code-generated client struct wrapping it.
 #[derive(Debug, Clone)]
 pub struct SingletonGrpcChannel {
-    buffer: BoxCloneSyncService<Request<Body>, Response<Incoming>, BoxError>,
+    service: BoxGrpcService,
+    metrics: Arc<ChannelMetrics>,
+}
+
+impl SingletonGrpcChannel {
+    /// Take a snapshot of the [ChannelStats] of this [SingletonGrpcChannel], which are shared by all of its clones.
+    pub fn stats(&self) -> ChannelStats {
+        self.metrics.snapshot(0)
+    }
 }
 
 impl Service<Request<Body>> for SingletonGrpcChannel {
-    type Response = Response<Incoming>;
+    type Response = Response<Body>;
 
     type Error = BoxError;
 
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.buffer.poll_ready(cx)
+        self.service.poll_ready(cx)
     }
 
     fn call(&mut self, request: Request<Body>) -> Self::Future {
-        self.buffer.call(request)
+        self.service.call(request)
+    }
+}
+
+#[cfg(all(test, feature = "unix-transport"))]
+mod tests {
+    use std::{path::Path, sync::Mutex, task::ready};
+
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+    use tokio::{
+        io::{AsyncRead, AsyncWrite, ReadBuf},
+        net::{UnixListener, UnixStream},
+        sync::oneshot,
+    };
+
+    use super::*;
+    use crate::GrpcConnectorBuilder;
+
+    /// The type of HTTP/2 RST_STREAM frames.
+    const RST_STREAM_FRAME: u8 = 0x3;
+
+    /// The type of HTTP/2 GOAWAY frames.
+    const GOAWAY_FRAME: u8 = 0x7;
+
+    /// A server-side I/O wrapper recording every byte received from the client.
+    struct RecordingIo {
+        io: UnixStream,
+        received: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl AsyncRead for RecordingIo {
+        fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            let this = self.get_mut();
+            let filled = buf.filled().len();
+            ready!(Pin::new(&mut this.io).poll_read(cx, buf))?;
+            this.received.lock().unwrap().extend_from_slice(&buf.filled()[filled..]);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for RecordingIo {
+        fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.get_mut().io).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.get_mut().io).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+        }
+    }
+
+    /// Split the bytes sent by an HTTP/2 client after its connection preface into the types and payloads of its frames.
+    fn frames(mut bytes: &[u8]) -> Vec<(u8, Vec<u8>)> {
+        bytes = &bytes[24..];
+        let mut frames = Vec::new();
+
+        while bytes.len() >= 9 {
+            let len = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize;
+            frames.push((bytes[3], bytes[9..9 + len].to_vec()));
+            bytes = &bytes[9 + len..];
+        }
+
+        frames
+    }
+
+    /// Spawn an HTTP/2 server on a Unix socket at the given path, answering every request on the first accepted
+    /// connection with a successful gRPC response and reporting all bytes received on it once it was closed.
+    fn spawn_server(socket_path: &Path) -> oneshot::Receiver<Vec<u8>> {
+        let listener = UnixListener::bind(socket_path).unwrap();
+        let (sender, receiver) = oneshot::channel();
+
+        tokio::spawn(async move {
+            let (io, _) = listener.accept().await.unwrap();
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let io = RecordingIo {
+                io,
+                received: received.clone(),
+            };
+            let mut connection = h2::server::handshake(io).await.unwrap();
+
+            while let Some(Ok((_, mut respond))) = connection.accept().await {
+                let response = Response::builder()
+                    .header("content-type", "application/grpc")
+                    .body(())
+                    .unwrap();
+                let mut stream = respond.send_response(response, false).unwrap();
+                let mut trailers = http::HeaderMap::new();
+                trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+                stream.send_trailers(trailers).unwrap();
+            }
+
+            let _ = sender.send(received.lock().unwrap().clone());
+        });
+
+        receiver
+    }
+
+    #[tokio::test]
+    async fn counts_streaming_response_as_in_flight_until_it_ends() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("grpc.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+        let (end_sender, end_receiver) = oneshot::channel::<()>();
+
+        tokio::spawn(async move {
+            let (io, _) = listener.accept().await.unwrap();
+            let mut connection = h2::server::handshake(io).await.unwrap();
+            let (_, mut respond) = connection.accept().await.unwrap().unwrap();
+            tokio::spawn(async move { while connection.accept().await.is_some() {} });
+
+            let response = Response::builder()
+                .header("content-type", "application/grpc")
+                .body(())
+                .unwrap();
+            let mut stream = respond.send_response(response, false).unwrap();
+            stream.send_data(Bytes::from_static(b"message"), false).unwrap();
+
+            let _ = end_receiver.await;
+            let mut trailers = http::HeaderMap::new();
+            trailers.insert("grpc-status", http::HeaderValue::from_static("0"));
+            stream.send_trailers(trailers).unwrap();
+        });
+
+        let mut channel =
+            SingletonGrpcChannelBuilder::new(16).build(GrpcConnectorBuilder::new().build_to_unix_socket(&socket_path));
+        let request = Request::builder()
+            .uri("http://localhost/test.Service/Method")
+            .body(Body::empty())
+            .unwrap();
+        let in_flight_streams = |channel: &SingletonGrpcChannel| channel.stats().connections[0].in_flight_streams;
+
+        let mut body = channel.ready().await.unwrap().call(request).await.unwrap().into_body();
+        assert_eq!(in_flight_streams(&channel), 1);
+
+        let data = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!(data, "message");
+        assert_eq!(in_flight_streams(&channel), 1);
+
+        end_sender.send(()).unwrap();
+        let trailers = body.frame().await.unwrap().unwrap().into_trailers().unwrap();
+        assert_eq!(trailers["grpc-status"], "0");
+        assert_eq!(in_flight_streams(&channel), 0);
+    }
+
+    #[tokio::test]
+    async fn closes_idle_connection_with_goaway() {
+        let directory = tempfile::tempdir().unwrap();
+        let socket_path = directory.path().join("grpc.sock");
+        let received = spawn_server(&socket_path);
+
+        let mut channel = SingletonGrpcChannelBuilder::new(16)
+            .idle_timeout(Duration::from_millis(100))
+            .build(GrpcConnectorBuilder::new().build_to_unix_socket(&socket_path));
+        let request = Request::builder()
+            .uri("http://localhost/test.Service/Method")
+            .body(Body::new(Full::new(Bytes::from_static(b"request"))))
+            .unwrap();
+        let response = channel.ready().await.unwrap().call(request).await.unwrap();
+        response.into_body().collect().await.unwrap();
+        assert_eq!(channel.stats().connections.len(), 1);
+
+        let received = tokio::time::timeout(Duration::from_secs(5), received)
+            .await
+            .unwrap()
+            .unwrap();
+        let frames = frames(&received);
+        assert!(frames.iter().all(|(frame_type, _)| *frame_type != RST_STREAM_FRAME));
+        let go_away = frames
+            .into_iter()
+            .find(|(frame_type, _)| *frame_type == GOAWAY_FRAME)
+            .map(|(_, payload)| payload)
+            .expect("the server received no GOAWAY frame");
+        assert_eq!(go_away[4..8], [0, 0, 0, 0], "the GOAWAY frame carries an error code");
+
+        let stats = channel.stats();
+        assert!(stats.connections.is_empty());
+        assert!(stats.last_error.is_none());
     }
 }
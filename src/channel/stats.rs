@@ -0,0 +1,181 @@
+use std::{
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use crate::channel::connection::ConnectionActivity;
+
+/// A point-in-time snapshot of the statistics of a gRPC channel, returned by
+/// [SingletonGrpcChannel::stats](crate::SingletonGrpcChannel::stats) and
+/// [PooledGrpcChannel::stats](crate::PooledGrpcChannel::stats). All counters are cumulative over the lifetime of the
+/// channel and shared by all of its clones.
+#[derive(Debug, Clone)]
+pub struct ChannelStats {
+    /// The currently open connections of the channel, including ones that no longer receive new requests but are still
+    /// finishing their in-flight requests.
+    pub connections: Vec<ConnectionStats>,
+    /// The number of requests waiting in the buffer of a singleton channel, or waiting for a connection to be opened in
+    /// a pooled channel.
+    pub pending_requests: usize,
+    /// The number of connections that were successfully opened.
+    pub total_connects: u64,
+    /// The number of attempts to open a connection that failed.
+    pub connect_failures: u64,
+    /// The number of attempts to open a connection in place of one that was closed or retired, regardless of their
+    /// outcome.
+    pub reconnects: u64,
+    /// The number of bytes sent over all connections.
+    pub bytes_sent: u64,
+    /// The number of bytes received over all connections.
+    pub bytes_received: u64,
+    /// The last connection or transport error that occurred on the channel, if any.
+    pub last_error: Option<RecordedError>,
+}
+
+/// A point-in-time snapshot of the statistics of a single open connection of a channel, as part of [ChannelStats].
+#[derive(Debug, Clone)]
+pub struct ConnectionStats {
+    /// The number of streams on the connection whose response has not finished yet, with a streaming response counting
+    /// until its body ends with its trailers, fails or is dropped.
+    pub in_flight_streams: usize,
+    /// The time elapsed since the connection was opened.
+    pub age: Duration,
+    /// Whether the connection no longer receives new requests, such as after the server sent a GOAWAY frame.
+    pub draining: bool,
+    /// The number of bytes sent over the connection.
+    pub bytes_sent: u64,
+    /// The number of bytes received over the connection.
+    pub bytes_received: u64,
+}
+
+/// An error recorded by a gRPC channel along with the time it occurred at, as part of [ChannelStats].
+#[derive(Debug, Clone)]
+pub struct RecordedError {
+    /// The [Display](std::fmt::Display) representation of the error.
+    pub message: String,
+    /// The wall-clock time at which the error was recorded.
+    pub at: SystemTime,
+}
+
+/// The metrics of a channel, shared by its clones, its connections and the tasks driving them.
+#[derive(Debug, Default)]
+pub(crate) struct ChannelMetrics {
+    connections: Mutex<Vec<Weak<ConnectionActivity>>>,
+    buffered_requests: AtomicUsize,
+    connects: AtomicU64,
+    connect_failures: AtomicU64,
+    reconnects: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    last_error: Mutex<Option<RecordedError>>,
+}
+
+impl ChannelMetrics {
+    /// Register a new connection, which is reported for as long as its [ConnectionActivity] is alive.
+    pub(crate) fn register_connection(&self, activity: &Arc<ConnectionActivity>) {
+        let mut connections = self.connections.lock().expect("channel metrics mutex was poisoned");
+        connections.retain(|connection| connection.strong_count() > 0);
+        connections.push(Arc::downgrade(activity));
+    }
+
+    /// Record the outcome of an attempt to open a connection, which may be a reconnect.
+    pub(crate) fn record_connect<E: std::fmt::Display>(&self, result: Result<(), &E>, reconnect: bool) {
+        if reconnect {
+            self.reconnects.fetch_add(1, Ordering::Relaxed);
+        }
+
+        match result {
+            Ok(()) => {
+                self.connects.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(err) => {
+                self.connect_failures.fetch_add(1, Ordering::Relaxed);
+                self.record_error(err);
+            }
+        }
+    }
+
+    /// Whether a connection has been successfully opened before, so that the next one is a reconnect.
+    #[cfg(feature = "singleton-channel")]
+    pub(crate) fn has_connected(&self) -> bool {
+        self.connects.load(Ordering::Relaxed) > 0
+    }
+
+    pub(crate) fn record_error<E: std::fmt::Display + ?Sized>(&self, err: &E) {
+        *self.last_error.lock().expect("channel metrics mutex was poisoned") = Some(RecordedError {
+            message: err.to_string(),
+            at: SystemTime::now(),
+        });
+    }
+
+    pub(crate) fn record_sent(&self, bytes: usize) {
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_received(&self, bytes: usize) {
+        self.bytes_received.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Mark a request as waiting in the buffer of a singleton channel until the returned [BufferedRequest] and all of
+    /// its clones are dropped.
+    #[cfg(feature = "singleton-channel")]
+    pub(crate) fn buffered_request(self: &Arc<Self>) -> BufferedRequest {
+        self.buffered_requests.fetch_add(1, Ordering::Relaxed);
+        BufferedRequest {
+            _slot: Arc::new(BufferSlot { metrics: self.clone() }),
+        }
+    }
+
+    /// Take a snapshot of these metrics, along with the given number of requests waiting for a connection that are
+    /// tracked outside of them.
+    pub(crate) fn snapshot(&self, waiting_requests: usize) -> ChannelStats {
+        let connections = self
+            .connections
+            .lock()
+            .expect("channel metrics mutex was poisoned")
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|activity| !activity.is_closed())
+            .map(|activity| activity.stats())
+            .collect();
+
+        ChannelStats {
+            connections,
+            pending_requests: self.buffered_requests.load(Ordering::Relaxed) + waiting_requests,
+            total_connects: self.connects.load(Ordering::Relaxed),
+            connect_failures: self.connect_failures.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            last_error: self
+                .last_error
+                .lock()
+                .expect("channel metrics mutex was poisoned")
+                .clone(),
+        }
+    }
+}
+
+/// A marker inserted into the extensions of a request that is sent into the buffer of a singleton channel, which counts
+/// the request as buffered until it is removed by the service sending it over a connection, or dropped along with the
+/// request.
+#[cfg(feature = "singleton-channel")]
+#[derive(Clone)]
+pub(crate) struct BufferedRequest {
+    _slot: Arc<BufferSlot>,
+}
+
+#[cfg(feature = "singleton-channel")]
+struct BufferSlot {
+    metrics: Arc<ChannelMetrics>,
+}
+
+#[cfg(feature = "singleton-channel")]
+impl Drop for BufferSlot {
+    fn drop(&mut self) {
+        self.metrics.buffered_requests.fetch_sub(1, Ordering::Relaxed);
+    }
+}
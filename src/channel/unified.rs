@@ -0,0 +1,288 @@
+use std::task::{Context, Poll};
+
+use http::{Request, Response};
+use tonic::body::Body;
+use tower::{BoxError, Layer, Service, ServiceExt, util::BoxCloneSyncService};
+
+use crate::{
+    BoxResultFuture, ConfigError, GrpcConnector,
+    channel::{BoxGrpcService, LayerStack},
+};
+
+/// The kind of channel built by a [GrpcChannelBuilder], allowing the kind to be selected at runtime, such as via
+/// configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrpcChannelKind {
+    /// A [crate::SingletonGrpcChannel] with a buffer of the given size.
+    #[cfg(feature = "singleton-channel")]
+    Singleton { buffer_size: usize },
+    /// A [crate::PooledGrpcChannel].
+    #[cfg(feature = "pooled-channel")]
+    Pooled,
+}
+
+/// A builder for a [GrpcChannel] of any [GrpcChannelKind], wrapping the builder of the respective channel
+/// implementation. Kind-specific options can be set on that builder before converting it into a [GrpcChannelBuilder]
+/// via [From], while options shared by all kinds can be set directly on the [GrpcChannelBuilder].
+#[derive(Debug, Clone)]
+pub struct GrpcChannelBuilder {
+    inner: GrpcChannelBuilderInner,
+    layers: LayerStack,
+}
+
+#[derive(Debug, Clone)]
+enum GrpcChannelBuilderInner {
+    #[cfg(feature = "singleton-channel")]
+    Singleton(crate::SingletonGrpcChannelBuilder),
+    #[cfg(feature = "pooled-channel")]
+    Pooled(crate::PooledGrpcChannelBuilder),
+}
+
+impl GrpcChannelBuilder {
+    /// Create a new [GrpcChannelBuilder] for the given [GrpcChannelKind] with default options.
+    pub fn new(kind: GrpcChannelKind) -> Self {
+        match kind {
+            #[cfg(feature = "singleton-channel")]
+            GrpcChannelKind::Singleton { buffer_size } => crate::SingletonGrpcChannelBuilder::new(buffer_size).into(),
+            #[cfg(feature = "pooled-channel")]
+            GrpcChannelKind::Pooled => crate::PooledGrpcChannelBuilder::new().into(),
+        }
+    }
+
+    /// Set a timeout [Duration](std::time::Duration) for all requests performed on the resulting [GrpcChannel].
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.inner = match self.inner {
+            #[cfg(feature = "singleton-channel")]
+            GrpcChannelBuilderInner::Singleton(builder) => GrpcChannelBuilderInner::Singleton(builder.timeout(timeout)),
+            #[cfg(feature = "pooled-channel")]
+            GrpcChannelBuilderInner::Pooled(builder) => GrpcChannelBuilderInner::Pooled(builder.timeout(timeout)),
+        };
+        self
+    }
+
+    /// Set a maximum age [Duration](std::time::Duration) of the connections of the resulting [GrpcChannel], after which
+    /// a connection is replaced by a new one while its in-flight requests are allowed to finish.
+    pub fn max_connection_age(mut self, age: std::time::Duration) -> Self {
+        self.inner = match self.inner {
+            #[cfg(feature = "singleton-channel")]
+            GrpcChannelBuilderInner::Singleton(builder) => {
+                GrpcChannelBuilderInner::Singleton(builder.max_connection_age(age))
+            }
+            #[cfg(feature = "pooled-channel")]
+            GrpcChannelBuilderInner::Pooled(builder) => {
+                GrpcChannelBuilderInner::Pooled(builder.max_connection_age(age))
+            }
+        };
+        self
+    }
+
+    /// Set a grace period [Duration](std::time::Duration) for in-flight requests on a connection that reached its
+    /// [GrpcChannelBuilder::max_connection_age], after which the connection is forcibly closed. Has no effect unless a
+    /// maximum age is set.
+    pub fn max_connection_age_grace(mut self, grace: std::time::Duration) -> Self {
+        self.inner = match self.inner {
+            #[cfg(feature = "singleton-channel")]
+            GrpcChannelBuilderInner::Singleton(builder) => {
+                GrpcChannelBuilderInner::Singleton(builder.max_connection_age_grace(grace))
+            }
+            #[cfg(feature = "pooled-channel")]
+            GrpcChannelBuilderInner::Pooled(builder) => {
+                GrpcChannelBuilderInner::Pooled(builder.max_connection_age_grace(grace))
+            }
+        };
+        self
+    }
+
+    /// Add a per-request tower [Layer] that is applied above the channel implementation, regardless of its kind. Layers
+    /// are applied in the order of [tower::ServiceBuilder], with the layer added first being the outermost one.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxGrpcService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Build a [GrpcChannel] backed by the given [GrpcConnector].
+    ///
+    /// # Panics
+    ///
+    /// Panics if any option set on the wrapped builder is invalid. Use [GrpcChannelBuilder::try_build] to handle
+    /// invalid options as a [ConfigError] instead.
+    pub fn build(self, connector: GrpcConnector) -> GrpcChannel {
+        match self.try_build(connector) {
+            Ok(channel) => channel,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Build a [GrpcChannel] backed by the given [GrpcConnector], emitting a [ConfigError] for the first invalid option
+    /// that was set on the wrapped builder.
+    pub fn try_build(self, connector: GrpcConnector) -> Result<GrpcChannel, ConfigError> {
+        let channel = match self.inner {
+            #[cfg(feature = "singleton-channel")]
+            GrpcChannelBuilderInner::Singleton(builder) => GrpcChannel::from(builder.try_build(connector)?),
+            #[cfg(feature = "pooled-channel")]
+            GrpcChannelBuilderInner::Pooled(builder) => GrpcChannel::from(builder.try_build(connector)?),
+        };
+
+        Ok(GrpcChannel {
+            service: self.layers.apply(channel.service),
+        })
+    }
+
+    /// Emit a [ConfigError] for the first invalid option that was set on the wrapped builder.
+    #[cfg_attr(not(feature = "serde"), allow(unused))]
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        match self.inner {
+            #[cfg(feature = "singleton-channel")]
+            GrpcChannelBuilderInner::Singleton(ref builder) => builder.validate(),
+            #[cfg(feature = "pooled-channel")]
+            GrpcChannelBuilderInner::Pooled(ref builder) => builder.validate(),
+        }
+    }
+
+    /// Build a [GrpcChannel] backed by a [GrpcConnector] to the given gRPC target string, as described by
+    /// [GrpcConnector::from_target], emitting a [ConfigError] for the `target` field if the target is invalid, or for
+    /// the first invalid option that was set on the wrapped builder. To inspect the [crate::GrpcTargetError] of an
+    /// invalid target, create the [GrpcConnector] via [GrpcConnector::from_target] and pass it to
+    /// [GrpcChannelBuilder::try_build] instead.
+    pub fn build_to_target(self, target: &str) -> Result<GrpcChannel, ConfigError> {
+        let connector =
+            GrpcConnector::from_target(target).map_err(|err| ConfigError::new("target", err.to_string()))?;
+        self.try_build(connector)
+    }
+}
+
+#[cfg(feature = "singleton-channel")]
+impl From<crate::SingletonGrpcChannelBuilder> for GrpcChannelBuilder {
+    fn from(value: crate::SingletonGrpcChannelBuilder) -> Self {
+        Self {
+            inner: GrpcChannelBuilderInner::Singleton(value),
+            layers: LayerStack::default(),
+        }
+    }
+}
+
+#[cfg(feature = "pooled-channel")]
+impl From<crate::PooledGrpcChannelBuilder> for GrpcChannelBuilder {
+    fn from(value: crate::PooledGrpcChannelBuilder) -> Self {
+        Self {
+            inner: GrpcChannelBuilderInner::Pooled(value),
+            layers: LayerStack::default(),
+        }
+    }
+}
+
+/// A gRPC channel [Service] compatible with [tonic] that type-erases any of this crate's channel implementations, or
+/// any other [Service] with the same request, response and error types, so that applications can switch between them
+/// without generics. This struct is cheaply [Clone]-able. To use this channel with [tonic] for performing requests,
+/// create a [tonic::client::Grpc] instance wrapping it or a code-generated client struct wrapping it.
+#[derive(Debug, Clone)]
+pub struct GrpcChannel {
+    service: BoxGrpcService,
+}
+
+impl GrpcChannel {
+    /// Create a [GrpcChannel] from a custom [Service], such as a channel implementation outside of this crate.
+    pub fn new<S>(service: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + Sync + 'static,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+    {
+        Self {
+            service: BoxCloneSyncService::new(service.map_err(Into::into)),
+        }
+    }
+}
+
+#[cfg(feature = "singleton-channel")]
+impl From<crate::SingletonGrpcChannel> for GrpcChannel {
+    fn from(value: crate::SingletonGrpcChannel) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(feature = "pooled-channel")]
+impl From<crate::PooledGrpcChannel> for GrpcChannel {
+    fn from(value: crate::PooledGrpcChannel) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(feature = "keyed-channel")]
+impl From<crate::KeyedGrpcChannel> for GrpcChannel {
+    fn from(value: crate::KeyedGrpcChannel) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Service<Request<Body>> for GrpcChannel {
+    type Response = Response<Body>;
+
+    type Error = BoxError;
+
+    type Future = BoxResultFuture<Response<Body>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        self.service.call(request)
+    }
+}
+
+#[cfg(all(test, feature = "singleton-channel", feature = "unix-transport"))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn build_to_target_reports_invalid_targets_and_options() {
+        let err = GrpcChannelBuilder::new(GrpcChannelKind::Singleton { buffer_size: 1024 })
+            .build_to_target("unix:")
+            .err()
+            .unwrap();
+        assert_eq!(err.field, "target");
+
+        let err = GrpcChannelBuilder::new(GrpcChannelKind::Singleton { buffer_size: 0 })
+            .build_to_target("unix:/tmp/test.sock")
+            .err()
+            .unwrap();
+        assert_eq!(err, ConfigError::new("buffer_size", "must be greater than 0"));
+
+        GrpcChannelBuilder::new(GrpcChannelKind::Singleton { buffer_size: 1024 })
+            .build_to_target("unix:/tmp/test.sock")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn build_accepts_options_without_effect() {
+        let http2 = crate::Http2Config {
+            keep_alive_timeout: Some(std::time::Duration::from_secs(10)),
+            keep_alive_while_idle: Some(true),
+            ..Default::default()
+        };
+        let singleton = crate::SingletonGrpcChannelBuilder::new(1024)
+            .http2(http2)
+            .max_connection_age_grace(std::time::Duration::from_secs(10));
+        GrpcChannelBuilder::from(singleton)
+            .build_to_target("unix:/tmp/test.sock")
+            .unwrap();
+
+        #[cfg(feature = "pooled-channel")]
+        {
+            let pooled = crate::PooledGrpcChannelBuilder::new()
+                .pool_idle_timeout(std::time::Duration::ZERO)
+                .http2_keep_alive_timeout(std::time::Duration::from_secs(10))
+                .max_connection_age_grace(std::time::Duration::from_secs(10));
+            GrpcChannelBuilder::from(pooled)
+                .build_to_target("unix:/tmp/test.sock")
+                .unwrap();
+        }
+    }
+}
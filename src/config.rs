@@ -0,0 +1,386 @@
+use std::time::Duration;
+
+use crate::{ConfigError, GrpcChannel, GrpcChannelBuilder, GrpcConnector, GrpcConnectorBuilder, GrpcTarget};
+
+/// A declarative configuration of a [GrpcChannel] and its [GrpcConnector], deserializable via [serde] from formats
+/// such as TOML, YAML or JSON, with [Duration]s written in a human-readable form such as `30s` or `1m 30s`. Unknown
+/// fields are rejected. A ready [GrpcChannel] is built via [ChannelConfig::build], which validates the configuration.
+///
+/// In TOML, a configuration looks as follows, with only the gRPC target string (described by [GrpcTarget]) being required:
+///
+/// ```toml
+/// target = "dns:///example.com:50051"
+/// timeout = "10s"
+///
+/// [channel]
+/// kind = "pooled"
+/// pool_idle_timeout = "90s"
+///
+/// [http2]
+/// keep_alive_interval = "30s"
+/// ```
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelConfig {
+    /// The gRPC target string to connect to.
+    pub target: String,
+    /// A timeout for all requests performed on the channel.
+    #[serde(default, with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+    /// A maximum age of every connection of the channel, after which it is replaced by a new connection.
+    #[serde(default, with = "humantime_serde")]
+    pub max_connection_age: Option<Duration>,
+    /// A grace period for in-flight requests on a connection that reached its maximum age.
+    #[serde(default, with = "humantime_serde")]
+    pub max_connection_age_grace: Option<Duration>,
+    /// Options of the [GrpcConnector].
+    #[serde(default)]
+    pub connector: ConnectorConfig,
+    /// Options of TCP connections made to `dns:`, `ipv4:` and `ipv6:` targets.
+    #[cfg(any(feature = "dns-tcp-transport", feature = "dns-tcp-tls-transport"))]
+    #[serde(default)]
+    pub tcp: crate::TcpConfig,
+    /// TLS options for `dns:` targets, which are connected to without TLS if omitted.
+    #[cfg(feature = "dns-tcp-tls-transport")]
+    #[serde(default)]
+    pub tls: Option<TlsFilesConfig>,
+    /// The kind of the channel and its kind-specific options.
+    #[serde(default)]
+    pub channel: ChannelKindConfig,
+    /// HTTP/2 options of the channel's connections.
+    #[serde(default)]
+    pub http2: crate::Http2Config,
+}
+
+/// Options of the [GrpcConnector] built from a [ChannelConfig].
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectorConfig {
+    /// A timeout for all connection attempts.
+    #[serde(with = "humantime_serde")]
+    pub timeout: Option<Duration>,
+}
+
+/// Paths to PEM-encoded files used for TLS by a [ChannelConfig], being the CA certificates used to verify the server and
+/// optionally a client certificate chain and its private key for mutual TLS. The [rustls::crypto::CryptoProvider]
+/// installed as the process-level default is used.
+#[cfg(feature = "dns-tcp-tls-transport")]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsFilesConfig {
+    pub ca_path: std::path::PathBuf,
+    #[serde(default)]
+    pub cert_chain_path: Option<std::path::PathBuf>,
+    #[serde(default)]
+    pub private_key_path: Option<std::path::PathBuf>,
+    #[serde(default = "default_tls_mode")]
+    pub mode: crate::TlsMode,
+}
+
+#[cfg(feature = "dns-tcp-tls-transport")]
+fn default_tls_mode() -> crate::TlsMode {
+    crate::TlsMode::Required
+}
+
+#[cfg(feature = "dns-tcp-tls-transport")]
+impl TlsFilesConfig {
+    fn build(&self) -> Result<crate::TlsConfig, ConfigError> {
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+
+        let provider = rustls::crypto::CryptoProvider::get_default()
+            .ok_or_else(|| ConfigError::new("tls", "no process-level rustls CryptoProvider is installed"))?
+            .clone();
+
+        let mut root_store = rustls::RootCertStore::empty();
+        let certificates = CertificateDer::pem_file_iter(&self.ca_path)
+            .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+            .map_err(|err| ConfigError::new("tls.ca_path", err.to_string()))?;
+        for certificate in certificates {
+            root_store
+                .add(certificate)
+                .map_err(|err| ConfigError::new("tls.ca_path", err.to_string()))?;
+        }
+
+        let builder = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|err| ConfigError::new("tls", err.to_string()))?
+            .with_root_certificates(root_store);
+
+        let config = match (&self.cert_chain_path, &self.private_key_path) {
+            (Some(cert_chain_path), Some(private_key_path)) => {
+                let cert_chain = CertificateDer::pem_file_iter(cert_chain_path)
+                    .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+                    .map_err(|err| ConfigError::new("tls.cert_chain_path", err.to_string()))?;
+                let private_key = PrivateKeyDer::from_pem_file(private_key_path)
+                    .map_err(|err| ConfigError::new("tls.private_key_path", err.to_string()))?;
+
+                builder
+                    .with_client_auth_cert(cert_chain, private_key)
+                    .map_err(|err| ConfigError::new("tls.private_key_path", err.to_string()))?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            (Some(_), None) => {
+                return Err(ConfigError::new(
+                    "tls.private_key_path",
+                    "must be set when tls.cert_chain_path is set",
+                ));
+            }
+            (None, Some(_)) => {
+                return Err(ConfigError::new(
+                    "tls.cert_chain_path",
+                    "must be set when tls.private_key_path is set",
+                ));
+            }
+        };
+
+        Ok(crate::TlsConfig::new(config, self.mode))
+    }
+}
+
+/// The kind of the channel built from a [ChannelConfig] and its kind-specific options, tagged by a `kind` field that is
+/// either `singleton` or `pooled`. Defaults to a pooled channel if the pooled channel is enabled.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ChannelKindConfig {
+    /// A [crate::SingletonGrpcChannel] with a buffer of the given size and the given connection options.
+    #[cfg(feature = "singleton-channel")]
+    Singleton {
+        #[serde(default = "default_buffer_size")]
+        buffer_size: usize,
+        #[serde(default, with = "humantime_serde")]
+        idle_timeout: Option<Duration>,
+    },
+    /// A [crate::PooledGrpcChannel] with the given pool options.
+    #[cfg(feature = "pooled-channel")]
+    Pooled {
+        #[serde(default, with = "humantime_serde")]
+        pool_idle_timeout: Option<Duration>,
+        #[serde(default)]
+        max_idle_connections: Option<usize>,
+        #[serde(default)]
+        streams_per_connection: Option<usize>,
+        #[serde(default)]
+        min_connections: Option<usize>,
+        #[serde(default)]
+        max_connections: Option<usize>,
+    },
+}
+
+#[cfg(feature = "singleton-channel")]
+fn default_buffer_size() -> usize {
+    1024
+}
+
+impl Default for ChannelKindConfig {
+    fn default() -> Self {
+        #[cfg(feature = "pooled-channel")]
+        return ChannelKindConfig::Pooled {
+            pool_idle_timeout: None,
+            max_idle_connections: None,
+            streams_per_connection: None,
+            min_connections: None,
+            max_connections: None,
+        };
+
+        #[cfg(not(feature = "pooled-channel"))]
+        return ChannelKindConfig::Singleton {
+            buffer_size: default_buffer_size(),
+            idle_timeout: None,
+        };
+    }
+}
+
+/// Map a [ConfigError] emitted by a channel builder onto the fields of a [ChannelConfig], in which the options specific
+/// to the kind of the channel are nested in `channel`.
+fn channel_config_error(mut err: ConfigError) -> ConfigError {
+    const TOP_LEVEL_FIELDS: [&str; 3] = ["timeout", "max_connection_age", "max_connection_age_grace"];
+
+    if !err.field.starts_with("http2.") && !TOP_LEVEL_FIELDS.contains(&err.field.as_str()) {
+        err.field = format!("channel.{}", err.field);
+    }
+
+    err
+}
+
+impl ChannelConfig {
+    /// Validate this [ChannelConfig] and build a [GrpcConnector] from it.
+    pub fn build_connector(&self) -> Result<GrpcConnector, ConfigError> {
+        let target = self
+            .target
+            .parse::<GrpcTarget>()
+            .map_err(|err| ConfigError::new("target", err.to_string()))?;
+
+        let mut connector_builder = GrpcConnectorBuilder::new();
+        if let Some(timeout) = self.connector.timeout {
+            connector_builder = connector_builder.timeout(timeout);
+        }
+
+        #[cfg(feature = "dns-tcp-tls-transport")]
+        if let Some(ref tls) = self.tls {
+            let GrpcTarget::Dns { host, port } = target else {
+                return Err(ConfigError::new("tls", "TLS is only supported for dns: targets"));
+            };
+
+            let uri = crate::target::dns_target_uri(&host, port)
+                .map_err(|err| ConfigError::new("target", err.to_string()))?;
+
+            return Ok(connector_builder.build_to_tcp_host_with_tls(
+                uri,
+                crate::DnsResolver::default(),
+                self.tcp.clone(),
+                tls.build()?,
+            ));
+        }
+
+        #[cfg(feature = "dns-tcp-transport")]
+        match target {
+            GrpcTarget::Dns { ref host, port } => {
+                let uri = crate::target::dns_target_uri(host, port)
+                    .map_err(|err| ConfigError::new("target", err.to_string()))?;
+
+                return Ok(connector_builder.build_to_tcp_host(uri, crate::DnsResolver::default(), self.tcp.clone()));
+            }
+            GrpcTarget::Ip(ref addresses) => {
+                return Ok(connector_builder.build_to_tcp_host(
+                    crate::target::ip_target_uri(),
+                    crate::target::static_dns_resolver(addresses),
+                    self.tcp.clone(),
+                ));
+            }
+            _ => {}
+        }
+
+        connector_builder
+            .build_to_target(&target)
+            .map_err(|err| ConfigError::new("target", err.to_string()))
+    }
+
+    /// Validate this [ChannelConfig] and build a [GrpcChannelBuilder] from it, on which layers can be added before
+    /// building the [GrpcChannel].
+    pub fn channel_builder(&self) -> Result<GrpcChannelBuilder, ConfigError> {
+        let mut channel_builder = match self.channel {
+            #[cfg(feature = "singleton-channel")]
+            ChannelKindConfig::Singleton {
+                buffer_size,
+                idle_timeout,
+            } => {
+                let mut builder = crate::SingletonGrpcChannelBuilder::new(buffer_size).http2(self.http2.clone());
+
+                if let Some(timeout) = idle_timeout {
+                    builder = builder.idle_timeout(timeout);
+                }
+
+                GrpcChannelBuilder::from(builder)
+            }
+            #[cfg(feature = "pooled-channel")]
+            ChannelKindConfig::Pooled {
+                pool_idle_timeout,
+                max_idle_connections,
+                streams_per_connection,
+                min_connections,
+                max_connections,
+            } => {
+                let mut builder = crate::PooledGrpcChannelBuilder::new().http2(self.http2.clone());
+
+                if let Some(timeout) = pool_idle_timeout {
+                    builder = builder.pool_idle_timeout(timeout);
+                }
+
+                if let Some(max) = max_idle_connections {
+                    builder = builder.max_idle_connections(max);
+                }
+
+                if let Some(target) = streams_per_connection {
+                    builder = builder.streams_per_connection(target);
+                }
+
+                if let Some(min) = min_connections {
+                    builder = builder.min_connections(min);
+                }
+
+                if let Some(max) = max_connections {
+                    builder = builder.max_connections(max);
+                }
+
+                GrpcChannelBuilder::from(builder)
+            }
+        };
+
+        if let Some(timeout) = self.timeout {
+            channel_builder = channel_builder.timeout(timeout);
+        }
+
+        if let Some(age) = self.max_connection_age {
+            channel_builder = channel_builder.max_connection_age(age);
+        }
+
+        if let Some(grace) = self.max_connection_age_grace {
+            channel_builder = channel_builder.max_connection_age_grace(grace);
+        }
+
+        channel_builder.validate().map_err(channel_config_error)?;
+        Ok(channel_builder)
+    }
+
+    /// Validate this [ChannelConfig] and build a ready [GrpcChannel] from it.
+    pub fn build(&self) -> Result<GrpcChannel, ConfigError> {
+        let connector = self.build_connector()?;
+        self.channel_builder()?
+            .try_build(connector)
+            .map_err(channel_config_error)
+    }
+}
+
+#[cfg(all(
+    test,
+    feature = "singleton-channel",
+    feature = "pooled-channel",
+    feature = "unix-transport"
+))]
+mod tests {
+    use super::*;
+
+    fn channel_config(config: serde_json::Value) -> ChannelConfig {
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn reports_builder_errors_with_config_fields() {
+        let cases = [
+            (
+                serde_json::json!({"channel": {"kind": "singleton", "buffer_size": 0}}),
+                "channel.buffer_size",
+            ),
+            (
+                serde_json::json!({"channel": {"kind": "pooled", "streams_per_connection": 0}}),
+                "channel.streams_per_connection",
+            ),
+            (
+                serde_json::json!({"channel": {"kind": "pooled", "min_connections": 2, "max_connections": 1}}),
+                "channel.min_connections",
+            ),
+            (serde_json::json!({"max_connection_age": "0s"}), "max_connection_age"),
+            (
+                serde_json::json!({"http2": {"max_frame_size": 1}}),
+                "http2.max_frame_size",
+            ),
+        ];
+
+        for (mut config, field) in cases {
+            config["target"] = "unix:/run/grpc.sock".into();
+            let err = channel_config(config).channel_builder().err().unwrap();
+            assert_eq!(err.field, field);
+        }
+    }
+
+    #[test]
+    fn accepts_valid_config() {
+        channel_config(serde_json::json!({
+            "target": "unix:/run/grpc.sock",
+            "timeout": "10s",
+            "channel": {"kind": "pooled", "min_connections": 1, "max_connections": 2},
+        }))
+        .channel_builder()
+        .unwrap();
+    }
+}
@@ -8,16 +8,29 @@ use http::Uri;
 use tower::ServiceExt;
 use tower::{BoxError, Service};
 
-#[cfg(feature = "__transport")]
+#[cfg(any(
+    feature = "dns-tcp-transport",
+    feature = "unix-transport",
+    feature = "vsock-transport"
+))]
 use crate::stream::GrpcStreamInner;
-use crate::{BoxResultFuture, stream::GrpcStream};
+use crate::{
+    BoxResultFuture,
+    handshake::{Handshake, HandshakeChain},
+    stream::GrpcStream,
+};
 
 /// A builder for a [GrpcConnector].
 #[derive(Debug, Clone)]
 pub struct GrpcConnectorBuilder {
     timeout: Option<Duration>,
-    #[cfg(feature = "firecracker-handshake")]
-    firecracker_handshake_port: Option<u32>,
+    handshakes: HandshakeChain,
+}
+
+impl Default for GrpcConnectorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GrpcConnectorBuilder {
@@ -25,8 +38,7 @@ impl GrpcConnectorBuilder {
     pub fn new() -> Self {
         Self {
             timeout: None,
-            #[cfg(feature = "firecracker-handshake")]
-            firecracker_handshake_port: None,
+            handshakes: HandshakeChain::default(),
         }
     }
 
@@ -36,15 +48,22 @@ impl GrpcConnectorBuilder {
         self
     }
 
+    /// Append a [Handshake] to the chain of handshakes performed as part of the connection process, after the transport has
+    /// connected and before HTTP/2 is negotiated. Handshakes are performed in the order they were added and can be combined
+    /// with all transports. With the DNS/TCP/TLS transport, they are performed on the raw TCP stream and TLS is negotiated
+    /// on top of the result.
+    pub fn handshake<H: Handshake>(mut self, handshake: H) -> Self {
+        self.handshakes.push(std::sync::Arc::new(handshake));
+        self
+    }
+
     /// Configure a Firecracker virtio-vsock handshake to the given guest port to be performed as part of the connection process.
-    /// Trying to build a [GrpcConnector] with the DNS/TCP/TLS transport and a Firecracker handshake enabled will result in a panic
-    /// in a debug build, and the handshake configuration will be silently ignored in a release build. Usually, this feature is used
-    /// in combination with the Unix transport, as Firecracker uses Unix sockets on the host, but it can be combined with all other
-    /// transports (except for DNS/TCP/TLS).
+    /// This is a shorthand for appending a [crate::FirecrackerHandshake] via [GrpcConnectorBuilder::handshake]. Usually, this
+    /// feature is used in combination with the Unix transport, as Firecracker uses Unix sockets on the host, but it can be
+    /// combined with all other transports.
     #[cfg(feature = "firecracker-handshake")]
-    pub fn perform_firecracker_handshake(mut self, port: u32) -> Self {
-        self.firecracker_handshake_port = Some(port);
-        self
+    pub fn perform_firecracker_handshake(self, port: u32) -> Self {
+        self.handshake(crate::handshake::FirecrackerHandshake::new(port))
     }
 
     /// Build a [GrpcConnector] that performs DNS resolution of a given [Uri] to an IP and connects to that
@@ -63,7 +82,7 @@ impl GrpcConnectorBuilder {
     }
 
     /// Build a [GrpcConnector] that performs DNS resolution of a given [Uri] to an IP and connects to that
-    /// IP over TCP with TLS.
+    /// IP over TCP with TLS, according to the [crate::TlsMode] of the given [crate::TlsConfig].
     #[cfg(feature = "dns-tcp-tls-transport")]
     pub fn build_to_tcp_host_with_tls(
         self,
@@ -72,24 +91,14 @@ impl GrpcConnectorBuilder {
         tcp_config: crate::tcp::TcpConfig,
         tls_config: crate::tls::TlsConfig,
     ) -> GrpcConnector {
-        #[cfg(feature = "firecracker-handshake")]
-        debug_assert!(
-            self.firecracker_handshake_port.is_none(),
-            "Firecracker handshake support in the alternate-tonic-client crate cannot be combined with the DNS/TCP/TLS transport."
-        );
+        let mut tcp_connector = tcp_config.build_connector(dns_resolver);
+        tcp_connector.enforce_http(false);
 
-        let connector = hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(tls_config.config);
-
-        let connector = match tls_config.require_tls {
-            true => connector.https_only(),
-            false => connector.https_or_http(),
-        };
-
-        let connector = connector
-            .enable_http2()
-            .wrap_connector(tcp_config.build_connector(dns_resolver));
-
-        self.build(GrpcConnectorInner::DnsTcpTls(uri, connector))
+        self.build(GrpcConnectorInner::DnsTcpTls(
+            uri,
+            tcp_connector,
+            crate::tls::TlsConnector::new(tls_config),
+        ))
     }
 
     /// Build a [GrpcConnector] that connects to the Unix socket located at the given path.
@@ -98,7 +107,10 @@ impl GrpcConnectorBuilder {
         self.build(GrpcConnectorInner::Unix(std::sync::Arc::new(socket_path.into())))
     }
 
-    /// Build a [GrpcConnector] that connects to a virtio-vsock socket identified by the given CID and port.
+    /// Build a [GrpcConnector] that connects to a virtio-vsock socket identified by the given CID and port. Well-known
+    /// CIDs are available as constants, such as [crate::VSOCK_CID_HOST] and [crate::VSOCK_CID_LOCAL]. Connection attempts
+    /// fail with a [crate::VsockConnectError], which is emitted immediately for reserved CIDs. Like with all other
+    /// transports, connection attempts are only bounded in time by [GrpcConnectorBuilder::timeout].
     #[cfg(feature = "vsock-transport")]
     pub fn build_to_vsock_socket(self, cid: u32, port: u32) -> GrpcConnector {
         self.build(GrpcConnectorInner::Vsock(cid, port))
@@ -124,8 +136,7 @@ impl GrpcConnectorBuilder {
         GrpcConnector {
             inner,
             timeout: self.timeout,
-            #[cfg(feature = "firecracker-handshake")]
-            firecracker_handshake_port: self.firecracker_handshake_port,
+            handshakes: self.handshakes,
         }
     }
 }
@@ -139,8 +150,8 @@ pub struct GrpcConnector {
     inner: GrpcConnectorInner,
     #[cfg_attr(not(feature = "__transport"), allow(unused))]
     timeout: Option<Duration>,
-    #[cfg(feature = "firecracker-handshake")]
-    firecracker_handshake_port: Option<u32>,
+    #[cfg_attr(not(feature = "__transport"), allow(unused))]
+    handshakes: HandshakeChain,
 }
 
 #[derive(Debug, Clone)]
@@ -153,7 +164,8 @@ enum GrpcConnectorInner {
     #[cfg(feature = "dns-tcp-tls-transport")]
     DnsTcpTls(
         Uri,
-        hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector<crate::dns::DnsResolver>>,
+        hyper_util::client::legacy::connect::HttpConnector<crate::dns::DnsResolver>,
+        crate::tls::TlsConnector,
     ),
     #[cfg(feature = "unix-transport")]
     Unix(std::sync::Arc<std::path::PathBuf>),
@@ -163,6 +175,40 @@ enum GrpcConnectorInner {
     Custom(tower::util::BoxCloneSyncService<(), GrpcStream, BoxError>),
 }
 
+/// The security of the transport used by a [GrpcConnector], which determines whether sensitive data such as per-RPC
+/// credentials can be sent over its connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportSecurity {
+    /// Connections are always encrypted and authenticated via TLS.
+    Tls,
+    /// Connections never leave the machine or its microVMs, as with the Unix and virtio-vsock transports.
+    Local,
+    /// Connections may be sent over the network unencrypted, as with the DNS/TCP transport, the DNS/TCP/TLS transport
+    /// when TLS is not required, and custom transports.
+    Plaintext,
+}
+
+impl GrpcConnector {
+    /// Get the [TransportSecurity] of the transport used by this [GrpcConnector].
+    pub fn transport_security(&self) -> TransportSecurity {
+        match self.inner {
+            #[cfg(feature = "dns-tcp-transport")]
+            GrpcConnectorInner::DnsTcp(..) => TransportSecurity::Plaintext,
+            #[cfg(feature = "dns-tcp-tls-transport")]
+            GrpcConnectorInner::DnsTcpTls(_, _, ref tls_connector) => match tls_connector.mode() {
+                crate::tls::TlsMode::Required => TransportSecurity::Tls,
+                crate::tls::TlsMode::Disabled | crate::tls::TlsMode::Opportunistic => TransportSecurity::Plaintext,
+            },
+            #[cfg(feature = "unix-transport")]
+            GrpcConnectorInner::Unix(_) => TransportSecurity::Local,
+            #[cfg(feature = "vsock-transport")]
+            GrpcConnectorInner::Vsock(..) => TransportSecurity::Local,
+            #[cfg(feature = "custom-transport")]
+            GrpcConnectorInner::Custom(_) => TransportSecurity::Plaintext,
+        }
+    }
+}
+
 impl Service<Uri> for GrpcConnector {
     type Response = GrpcStream;
 
@@ -180,7 +226,9 @@ impl Service<Uri> for GrpcConnector {
                 connector.poll_ready(cx).map_err(|err| Box::new(err) as BoxError)
             }
             #[cfg(feature = "dns-tcp-tls-transport")]
-            GrpcConnectorInner::DnsTcpTls(_, ref mut connector) => connector.poll_ready(cx),
+            GrpcConnectorInner::DnsTcpTls(_, ref mut connector, _) => {
+                connector.poll_ready(cx).map_err(|err| Box::new(err) as BoxError)
+            }
             #[cfg(feature = "unix-transport")]
             GrpcConnectorInner::Unix(_) => Poll::Ready(Ok(())),
             #[cfg(feature = "vsock-transport")]
@@ -192,12 +240,13 @@ impl Service<Uri> for GrpcConnector {
 
     fn call(&mut self, _uri: Uri) -> Self::Future {
         #[cfg(not(feature = "__transport"))]
-        panic!("alternate-tonic-client crate had no transport feature enabled at runtime");
+        return Box::pin(std::future::ready(Err(
+            "alternate-tonic-client crate had no transport feature enabled at runtime".into(),
+        )));
 
         #[cfg(feature = "__transport")]
         {
-            #[cfg(feature = "firecracker-handshake")]
-            let firecracker_handshake_port = self.firecracker_handshake_port;
+            let handshakes = self.handshakes.clone();
 
             let future: BoxResultFuture<GrpcStream> = match self.inner {
                 #[cfg(feature = "dns-tcp-transport")]
@@ -205,77 +254,96 @@ impl Service<Uri> for GrpcConnector {
                     let future = connector.call(uri.clone());
 
                     Box::pin(async move {
-                        #[cfg_attr(not(feature = "firecracker-handshake"), allow(unused_mut))]
                         let mut stream = future.await?;
-                        #[cfg(feature = "firecracker-handshake")]
-                        perform_firecracker_handshake(firecracker_handshake_port, stream.inner_mut()).await?;
+                        let info = handshakes.perform(stream.inner_mut()).await?;
 
                         Ok(GrpcStream {
                             inner: GrpcStreamInner::DnsTcp(stream),
+                            info,
                         })
                     })
                 }
                 #[cfg(feature = "dns-tcp-tls-transport")]
-                GrpcConnectorInner::DnsTcpTls(ref uri, ref mut connector) => {
+                GrpcConnectorInner::DnsTcpTls(ref uri, ref mut connector, ref tls_connector) => {
                     let future = connector.call(uri.clone());
-                    Box::pin(async move { future.await.map(GrpcStream::dns_tcp_tls) })
+                    let mut connector = connector.clone();
+                    let uri = uri.clone();
+                    let tls_connector = tls_connector.clone();
+
+                    Box::pin(async move {
+                        use crate::tls::{TlsConnector, TlsMode};
+
+                        let mut stream = future.await?;
+                        let info = handshakes.perform(stream.inner_mut()).await?;
+
+                        match tls_connector.mode() {
+                            TlsMode::Required => tls_connector.connect(&uri, stream.into_inner(), info).await,
+                            TlsMode::Disabled => Ok(TlsConnector::connect_plaintext(stream.into_inner(), info)),
+                            TlsMode::Opportunistic => {
+                                match tls_connector.connect(&uri, stream.into_inner(), info).await {
+                                    Ok(stream) => Ok(stream),
+                                    Err(err) if crate::tls::is_plaintext_server(&err) => {
+                                        tls_connector.fall_back(&err);
+
+                                        let mut stream = connector.call(uri).await?;
+                                        let info = handshakes.perform(stream.inner_mut()).await?;
+                                        Ok(TlsConnector::connect_plaintext(stream.into_inner(), info))
+                                    }
+                                    Err(err) => Err(err),
+                                }
+                            }
+                        }
+                    })
                 }
                 #[cfg(feature = "unix-transport")]
                 GrpcConnectorInner::Unix(ref socket_path) => {
                     let socket_path = socket_path.clone();
 
                     Box::pin(async move {
-                        #[cfg_attr(not(feature = "firecracker-handshake"), allow(unused_mut))]
                         let mut stream = tokio::net::UnixStream::connect(socket_path.as_ref()).await?;
-                        #[cfg(feature = "firecracker-handshake")]
-                        perform_firecracker_handshake(firecracker_handshake_port, &mut stream).await?;
+                        let info = handshakes.perform(&mut stream).await?;
 
                         Ok(GrpcStream {
                             inner: GrpcStreamInner::Unix(hyper_util::rt::tokio::WithHyperIo::new(stream)),
+                            info,
                         })
                     })
                 }
                 #[cfg(feature = "vsock-transport")]
                 GrpcConnectorInner::Vsock(cid, port) => Box::pin(async move {
-                    #[cfg_attr(not(feature = "firecracker-handshake"), allow(unused_mut))]
-                    let mut stream = tokio_vsock::VsockStream::connect(tokio_vsock::VsockAddr::new(cid, port)).await?;
-                    #[cfg(feature = "firecracker-handshake")]
-                    perform_firecracker_handshake(firecracker_handshake_port, &mut stream).await?;
+                    crate::vsock::VsockConnectError::validate_cid(cid)?;
+                    let mut stream = tokio_vsock::VsockStream::connect(tokio_vsock::VsockAddr::new(cid, port))
+                        .await
+                        .map_err(crate::vsock::VsockConnectError::from_io)?;
+                    let info = handshakes.perform(&mut stream).await?;
 
                     Ok(GrpcStream {
                         inner: GrpcStreamInner::Vsock(hyper_util::rt::tokio::WithHyperIo::new(stream)),
+                        info,
                     })
                 }),
                 #[cfg(feature = "custom-transport")]
                 GrpcConnectorInner::Custom(ref mut service) => {
-                    #[cfg(feature = "firecracker-handshake")]
-                    {
-                        let future = service.call(());
+                    let future = service.call(());
 
-                        Box::pin(async move {
-                            let mut stream = future.await?;
-
-                            perform_firecracker_handshake(
-                                firecracker_handshake_port,
-                                &mut hyper_util::rt::tokio::WithTokioIo::new(&mut stream),
-                            )
+                    Box::pin(async move {
+                        let mut stream = future.await?;
+                        let info = handshakes
+                            .perform(&mut hyper_util::rt::tokio::WithTokioIo::new(&mut stream))
                             .await?;
+                        stream.info.extend_from(info);
 
-                            Ok(stream)
-                        })
-                    }
-
-                    #[cfg(not(feature = "firecracker-handshake"))]
-                    service.call(())
+                        Ok(stream)
+                    })
                 }
             };
 
             match self.timeout {
                 Some(timeout) => Box::pin(async move {
-                    tokio::time::timeout(timeout, future)
-                        .await
-                        .map_err(|err| Box::new(err) as BoxError)
-                        .flatten()
+                    match tokio::time::timeout(timeout, future).await {
+                        Ok(result) => result,
+                        Err(err) => Err(Box::new(err) as BoxError),
+                    }
                 }),
                 None => future,
             }
@@ -283,37 +351,155 @@ impl Service<Uri> for GrpcConnector {
     }
 }
 
-#[cfg(feature = "firecracker-handshake")]
-async fn perform_firecracker_handshake<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(
-    port: Option<u32>,
-    stream: &mut S,
-) -> Result<(), std::io::Error> {
-    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-    const BUFFER_CAPACITY: usize = 14;
+#[cfg(all(test, feature = "dns-tcp-tls-transport"))]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
 
-    let Some(port) = port else {
-        return Ok(());
+    use tokio::io::AsyncWriteExt;
+
+    use crate::{
+        PinMismatchError, PinningMode, ServerIdentityMatcher, ServerIdentityMismatchError, SpkiPin, SpkiPins,
+        TlsConfig, TlsConnectionInfo, TlsMode,
+        test_util::{TestCa, server_config, spawn_plaintext_server, spawn_tls_server, tls_connector},
     };
 
-    stream.write_all(format!("CONNECT {port}\n").as_bytes()).await?;
+    use super::*;
 
-    let mut lines = BufReader::with_capacity(BUFFER_CAPACITY, stream).lines();
-    match lines.next_line().await {
-        Ok(Some(line)) => {
-            if !line.starts_with("OK") {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::ConnectionRefused,
-                    "Firecracker refused to establish a tunnel to the given guest port",
-                ));
-            }
+    /// The frame header of an HTTP/2 GOAWAY frame, as sent by a plaintext HTTP/2 server receiving a TLS handshake.
+    const HTTP2_GO_AWAY: &[u8] = b"\x00\x00\x08\x07\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01";
+
+    fn tls_config(ca: &TestCa, mode: TlsMode) -> TlsConfig {
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(ca.root_store())
+            .with_no_client_auth();
+        TlsConfig::new(config, mode)
+    }
+
+    /// Set a fallback hook on the given [TlsConfig], returning a counter of its invocations.
+    fn count_fallbacks(tls_config: TlsConfig) -> (TlsConfig, Arc<AtomicUsize>) {
+        let fallbacks = Arc::new(AtomicUsize::new(0));
+        let counter = fallbacks.clone();
+        let tls_config = tls_config.on_fallback(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        (tls_config, fallbacks)
+    }
+
+    async fn connect(connector: GrpcConnector) -> Result<GrpcStream, BoxError> {
+        connector.oneshot(Uri::from_static("http://localhost")).await
+    }
+
+    #[tokio::test]
+    async fn required_mode_uses_tls() {
+        let ca = TestCa::new("CA");
+        let (addr, _) = spawn_tls_server(server_config(&ca.issue(&["127.0.0.1"]), None)).await;
+
+        let stream = connect(tls_connector(addr, tls_config(&ca, TlsMode::Required)))
+            .await
+            .unwrap();
+        assert!(stream.connection_info().get::<TlsConnectionInfo>().is_some());
+    }
+
+    #[tokio::test]
+    async fn required_mode_rejects_plaintext_server() {
+        let ca = TestCa::new("CA");
+        let (addr, _) = spawn_plaintext_server(HTTP2_GO_AWAY).await;
+
+        assert!(
+            connect(tls_connector(addr, tls_config(&ca, TlsMode::Required)))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn disabled_mode_uses_plaintext() {
+        let ca = TestCa::new("CA");
+        let (addr, mut received) = spawn_plaintext_server(b"").await;
+
+        let mut stream = connect(tls_connector(addr, tls_config(&ca, TlsMode::Disabled)))
+            .await
+            .unwrap();
+        assert!(stream.connection_info().get::<TlsConnectionInfo>().is_none());
+
+        hyper_util::rt::TokioIo::new(&mut stream)
+            .write_all(b"ping")
+            .await
+            .unwrap();
+        assert_eq!(received.recv().await.unwrap(), b"ping");
+    }
+
+    #[tokio::test]
+    async fn opportunistic_mode_uses_tls_if_available() {
+        let ca = TestCa::new("CA");
+        let (addr, _) = spawn_tls_server(server_config(&ca.issue(&["127.0.0.1"]), None)).await;
+        let (tls_config, fallbacks) = count_fallbacks(tls_config(&ca, TlsMode::Opportunistic));
+
+        let stream = connect(tls_connector(addr, tls_config)).await.unwrap();
+        assert!(stream.connection_info().get::<TlsConnectionInfo>().is_some());
+        assert_eq!(fallbacks.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn opportunistic_mode_falls_back_for_plaintext_servers() {
+        let ca = TestCa::new("CA");
+
+        for reply in [HTTP2_GO_AWAY, b"HTTP/1.1 400 Bad Request\r\n\r\n", b""] {
+            let (addr, _) = spawn_plaintext_server(reply).await;
+            let (tls_config, fallbacks) = count_fallbacks(tls_config(&ca, TlsMode::Opportunistic));
+
+            let stream = connect(tls_connector(addr, tls_config)).await.unwrap();
+            assert!(stream.connection_info().get::<TlsConnectionInfo>().is_none());
+            assert_eq!(fallbacks.load(Ordering::SeqCst), 1, "{reply:?}");
         }
-        _ => {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Could not read Firecracker response",
-            ));
+    }
+
+    #[tokio::test]
+    async fn opportunistic_mode_does_not_fall_back_for_untrusted_certificates() {
+        let ca = TestCa::new("CA");
+        let untrusted_ca = TestCa::new("untrusted CA");
+        let (addr, _) = spawn_tls_server(server_config(&untrusted_ca.issue(&["127.0.0.1"]), None)).await;
+        let (tls_config, fallbacks) = count_fallbacks(tls_config(&ca, TlsMode::Opportunistic));
+
+        let err = connect(tls_connector(addr, tls_config)).await.err().unwrap();
+        let err = err.downcast_ref::<std::io::Error>().unwrap();
+        assert!(matches!(
+            err.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>()),
+            Some(rustls::Error::InvalidCertificate(_))
+        ));
+        assert_eq!(fallbacks.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn opportunistic_mode_does_not_fall_back_for_pin_mismatches() {
+        let ca = TestCa::new("CA");
+        let (addr, _) = spawn_tls_server(server_config(&ca.issue(&["127.0.0.1"]), None)).await;
+        let pins = SpkiPins::new([SpkiPin::of_certificate(&ca.issue(&["127.0.0.1"]).certificate).unwrap()]);
+
+        for mode in [PinningMode::WithVerification, PinningMode::Exclusive] {
+            let (tls_config, fallbacks) =
+                count_fallbacks(tls_config(&ca, TlsMode::Opportunistic).pin_public_keys(pins.clone(), mode));
+
+            let err = connect(tls_connector(addr, tls_config)).await.err().unwrap();
+            assert!(err.is::<PinMismatchError>(), "{mode:?}: {err}");
+            assert_eq!(fallbacks.load(Ordering::SeqCst), 0);
         }
-    };
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn opportunistic_mode_does_not_fall_back_for_identity_mismatches() {
+        let ca = TestCa::new("CA");
+        let (addr, _) = spawn_tls_server(server_config(&ca.issue(&["127.0.0.1"]), None)).await;
+        let (tls_config, fallbacks) = count_fallbacks(tls_config(&ca, TlsMode::Opportunistic).verify_server_identity(
+            ServerIdentityMatcher::DnsSan("other.example.com".to_string()),
+            ca.root_store(),
+        ));
+
+        let err = connect(tls_connector(addr, tls_config)).await.err().unwrap();
+        assert!(err.is::<ServerIdentityMismatchError>(), "{err}");
+        assert_eq!(fallbacks.load(Ordering::SeqCst), 0);
+    }
 }
@@ -0,0 +1,610 @@
+use std::{
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use http::{HeaderMap, HeaderValue, Request, Response, header::AUTHORIZATION};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use tonic::body::Body;
+use tower::{BoxError, Layer, Service, ServiceExt};
+
+use crate::{BoxResultFuture, TransportSecurity, replay};
+
+/// A token attached to requests as the value of their `authorization` metadata by a [CredentialsLayer].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    authorization: HeaderValue,
+    expires_at: Option<Instant>,
+}
+
+impl Token {
+    /// Create a new [Token] from the full value of the `authorization` metadata, such as `Bearer <token>`, and the
+    /// [Instant] it expires at, if it expires.
+    pub fn new(mut authorization: HeaderValue, expires_at: Option<Instant>) -> Self {
+        authorization.set_sensitive(true);
+        Self {
+            authorization,
+            expires_at,
+        }
+    }
+
+    /// Create a new bearer [Token] from an access token and the [Duration] it expires in, if it expires.
+    pub fn bearer(access_token: &str, expires_in: Option<Duration>) -> Result<Self, http::header::InvalidHeaderValue> {
+        Ok(Self::new(
+            HeaderValue::from_str(&format!("Bearer {access_token}"))?,
+            expires_in.map(|expires_in| Instant::now() + expires_in),
+        ))
+    }
+
+    /// Get the full value of the `authorization` metadata of this [Token].
+    pub fn authorization(&self) -> &HeaderValue {
+        &self.authorization
+    }
+
+    /// Get the [Instant] this [Token] expires at, if it expires.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.expires_at
+    }
+}
+
+/// A future returned by a [TokenSource], yielding either a [Token] or a boxed type-erased [std::error::Error].
+pub type TokenFuture<'a> = Pin<Box<dyn Future<Output = Result<Token, BoxError>> + Send + 'a>>;
+
+/// A source of [Token]s for per-RPC credentials, such as an OAuth2 token endpoint. A [TokenSource] is only asked for a
+/// new [Token] by a [CredentialsLayer] when the cached one is about to expire or was rejected by the server.
+pub trait TokenSource: Send + Sync + 'static {
+    /// Fetch a new [Token].
+    fn fetch_token(&self) -> TokenFuture<'_>;
+}
+
+/// A cache of the [Token] yielded by a [TokenSource], shared by all services of a [CredentialsLayer].
+struct TokenCache {
+    source: Arc<dyn TokenSource>,
+    refresh_margin: Duration,
+    token: Mutex<Option<Token>>,
+    fetch_lock: tokio::sync::Mutex<()>,
+    refreshing: AtomicBool,
+}
+
+impl TokenCache {
+    fn cached(&self) -> Option<Token> {
+        let now = Instant::now();
+        self.token
+            .lock()
+            .expect("token cache mutex was poisoned")
+            .clone()
+            .filter(|token| token.expires_at.is_none_or(|expires_at| expires_at > now))
+    }
+
+    fn needs_refresh(&self, token: &Token) -> bool {
+        token
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= Instant::now() + self.refresh_margin)
+    }
+
+    async fn get(self: &Arc<Self>) -> Result<Token, BoxError> {
+        if let Some(token) = self.cached() {
+            if self.needs_refresh(&token) {
+                self.spawn_refresh();
+            }
+
+            return Ok(token);
+        }
+
+        let _guard = self.fetch_lock.lock().await;
+        if let Some(token) = self.cached() {
+            return Ok(token);
+        }
+
+        self.fetch().await
+    }
+
+    fn spawn_refresh(self: &Arc<Self>) {
+        if self.refreshing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let cache = self.clone();
+        tokio::task::spawn(async move {
+            let _guard = cache.fetch_lock.lock().await;
+            if cache.cached().is_none_or(|token| cache.needs_refresh(&token)) {
+                let _ = cache.fetch().await;
+            }
+
+            cache.refreshing.store(false, Ordering::Release);
+        });
+    }
+
+    async fn fetch(&self) -> Result<Token, BoxError> {
+        let token = self.source.fetch_token().await?;
+        *self.token.lock().expect("token cache mutex was poisoned") = Some(token.clone());
+        Ok(token)
+    }
+
+    fn invalidate(&self, rejected_token: &Token) {
+        let mut token = self.token.lock().expect("token cache mutex was poisoned");
+        if token.as_ref() == Some(rejected_token) {
+            *token = None;
+        }
+    }
+}
+
+/// A tower [Layer] for gRPC channels that attaches per-RPC credentials from a [TokenSource] to every request as its
+/// `authorization` metadata. This struct is cheaply [Clone]-able, with all clones and the services they produce sharing
+/// the same [Token] cache.
+///
+/// Tokens are cached until they expire and are refreshed on a background [tokio] task once they are within the refresh
+/// margin of their expiry, so that requests rarely wait for a refresh. When the server responds with the
+/// `UNAUTHENTICATED` status, the cached token is discarded and the request is re-attempted once with a fresh token,
+/// provided that its body was sent completely and did not exceed the maximum replay size (which is the case for most
+/// unary requests). When the `UNAUTHENTICATED` status is only sent in the trailers of a response, such as by a
+/// streaming RPC, the response has already been handed over, so the cached token is discarded without re-attempting
+/// the request.
+///
+/// Credentials are only sent over transports with [TransportSecurity::Tls] or [TransportSecurity::Local], and requests
+/// over [TransportSecurity::Plaintext] fail unless allowed via [CredentialsLayer::allow_plaintext].
+#[derive(Clone)]
+pub struct CredentialsLayer {
+    cache: Arc<TokenCache>,
+    transport_security: TransportSecurity,
+    allow_plaintext: bool,
+    max_replay_size: usize,
+}
+
+impl std::fmt::Debug for CredentialsLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CredentialsLayer")
+            .field("transport_security", &self.transport_security)
+            .field("allow_plaintext", &self.allow_plaintext)
+            .field("max_replay_size", &self.max_replay_size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CredentialsLayer {
+    /// The default margin before a [Token]'s expiry at which it is refreshed in the background.
+    pub const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(30);
+
+    /// The default maximum size of a request body that is recorded for re-attempting the request.
+    pub const DEFAULT_MAX_REPLAY_SIZE: usize = 64 * 1024;
+
+    /// Create a new [CredentialsLayer] from a [TokenSource] and the [TransportSecurity] of the channel it is applied to,
+    /// which is usually obtained via [GrpcConnector::transport_security](crate::GrpcConnector::transport_security).
+    pub fn new<T: TokenSource>(token_source: T, transport_security: TransportSecurity) -> Self {
+        Self {
+            cache: Arc::new(TokenCache {
+                source: Arc::new(token_source),
+                refresh_margin: Self::DEFAULT_REFRESH_MARGIN,
+                token: Mutex::new(None),
+                fetch_lock: tokio::sync::Mutex::new(()),
+                refreshing: AtomicBool::new(false),
+            }),
+            transport_security,
+            allow_plaintext: false,
+            max_replay_size: Self::DEFAULT_MAX_REPLAY_SIZE,
+        }
+    }
+
+    /// Set the margin before a [Token]'s expiry at which it is refreshed in the background.
+    pub fn refresh_margin(mut self, refresh_margin: Duration) -> Self {
+        self.cache = Arc::new(TokenCache {
+            source: self.cache.source.clone(),
+            refresh_margin,
+            token: Mutex::new(None),
+            fetch_lock: tokio::sync::Mutex::new(()),
+            refreshing: AtomicBool::new(false),
+        });
+        self
+    }
+
+    /// Allow sending credentials over transports with [TransportSecurity::Plaintext].
+    pub fn allow_plaintext(mut self, allow_plaintext: bool) -> Self {
+        self.allow_plaintext = allow_plaintext;
+        self
+    }
+
+    /// Set the maximum size of a request body that is recorded for re-attempting the request on `UNAUTHENTICATED`.
+    pub fn max_replay_size(mut self, max_replay_size: usize) -> Self {
+        self.max_replay_size = max_replay_size;
+        self
+    }
+}
+
+impl<S> Layer<S> for CredentialsLayer {
+    type Service = CredentialsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CredentialsService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// A [Service] attaching per-RPC credentials to requests, produced by a [CredentialsLayer].
+#[derive(Debug, Clone)]
+pub struct CredentialsService<S> {
+    inner: S,
+    layer: CredentialsLayer,
+}
+
+fn is_unauthenticated(headers: &HeaderMap) -> bool {
+    headers
+        .get("grpc-status")
+        .is_some_and(|status| status.as_bytes() == b"16")
+}
+
+/// A response body wrapper that discards the cached [Token] its request was sent with once the trailers of the response
+/// carry the `UNAUTHENTICATED` status.
+struct UnauthenticatedTrailers {
+    inner: Body,
+    cache: Arc<TokenCache>,
+    token: Token,
+}
+
+impl HttpBody for UnauthenticatedTrailers {
+    type Data = Bytes;
+
+    type Error = tonic::Status;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+
+        if let Poll::Ready(Some(Ok(frame))) = &poll {
+            if frame.trailers_ref().is_some_and(is_unauthenticated) {
+                self.cache.invalidate(&self.token);
+            }
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Wrap the body of the given response in [UnauthenticatedTrailers].
+fn watch_trailers<B>(response: Response<B>, cache: &Arc<TokenCache>, token: Token) -> Response<Body>
+where
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    response.map(|body| {
+        Body::new(UnauthenticatedTrailers {
+            inner: Body::new(body),
+            cache: cache.clone(),
+            token,
+        })
+    })
+}
+
+impl<S, B> Service<Request<Body>> for CredentialsService<S>
+where
+    S: Service<Request<Body>, Response = Response<B>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send,
+    B: HttpBody<Data = Bytes> + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Response = Response<Body>;
+
+    type Error = BoxError;
+
+    type Future = BoxResultFuture<Response<Body>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+
+        Box::pin(async move {
+            if layer.transport_security == TransportSecurity::Plaintext && !layer.allow_plaintext {
+                return Err("Refusing to send per-RPC credentials over a plaintext transport".into());
+            }
+
+            let token = layer.cache.get().await?;
+            let (mut request, replay) = replay::record(request, layer.max_replay_size);
+            request.headers_mut().insert(AUTHORIZATION, token.authorization.clone());
+
+            let response = inner.call(request).await.map_err(Into::into)?;
+            if !is_unauthenticated(response.headers()) {
+                return Ok(watch_trailers(response, &layer.cache, token));
+            }
+
+            layer.cache.invalidate(&token);
+            let Some(mut request) = replay.replay() else {
+                return Ok(watch_trailers(response, &layer.cache, token));
+            };
+
+            let token = layer.cache.get().await?;
+            request.headers_mut().insert(AUTHORIZATION, token.authorization.clone());
+            let response = inner
+                .ready()
+                .await
+                .map_err(Into::into)?
+                .call(request)
+                .await
+                .map_err(Into::into)?;
+            Ok(watch_trailers(response, &layer.cache, token))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use http_body_util::{BodyExt, Empty, Full};
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    /// A [TokenSource] yielding `Bearer token-<n>` for the n-th fetch, with tokens expiring after the given [Duration].
+    #[derive(Clone)]
+    struct CountingTokenSource {
+        fetches: Arc<AtomicUsize>,
+        expires_in: Option<Duration>,
+    }
+
+    impl CountingTokenSource {
+        fn new(expires_in: Option<Duration>) -> Self {
+            Self {
+                fetches: Arc::new(AtomicUsize::new(0)),
+                expires_in,
+            }
+        }
+
+        fn fetches(&self) -> usize {
+            self.fetches.load(Ordering::SeqCst)
+        }
+    }
+
+    impl TokenSource for CountingTokenSource {
+        fn fetch_token(&self) -> TokenFuture<'_> {
+            Box::pin(async move {
+                let fetch = self.fetches.fetch_add(1, Ordering::SeqCst) + 1;
+                Ok(Token::bearer(&format!("token-{fetch}"), self.expires_in)?)
+            })
+        }
+    }
+
+    type MockServer = tower::util::BoxCloneSyncService<Request<Body>, Response<Body>, BoxError>;
+
+    /// How the mock server responds to a request.
+    #[derive(Clone, Copy)]
+    enum Reply {
+        Ok,
+        UnauthenticatedHeaders,
+        UnauthenticatedTrailers,
+    }
+
+    /// A mock gRPC server reading every request and answering it with the next [Reply], reporting the `authorization`
+    /// metadata of every request.
+    fn mock_server(replies: Vec<Reply>) -> (MockServer, mpsc::UnboundedReceiver<HeaderValue>) {
+        let replies = Arc::new(Mutex::new(replies.into_iter()));
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        let service = tower::service_fn(move |request: Request<Body>| {
+            let reply = replies.lock().unwrap().next().unwrap_or(Reply::Ok);
+            sender.send(request.headers()[AUTHORIZATION].clone()).unwrap();
+
+            async move {
+                request.into_body().collect().await?;
+
+                let mut response = Response::builder().header("content-type", "application/grpc");
+                let body = match reply {
+                    Reply::Ok => Body::new(Full::new(Bytes::from_static(b"response")).with_trailers(async {
+                        let mut trailers = HeaderMap::new();
+                        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+                        Some(Ok(trailers))
+                    })),
+                    Reply::UnauthenticatedHeaders => {
+                        response = response.header("grpc-status", "16");
+                        Body::new(Empty::<Bytes>::new())
+                    }
+                    Reply::UnauthenticatedTrailers => {
+                        Body::new(Full::new(Bytes::from_static(b"partial response")).with_trailers(async {
+                            let mut trailers = HeaderMap::new();
+                            trailers.insert("grpc-status", HeaderValue::from_static("16"));
+                            Some(Ok(trailers))
+                        }))
+                    }
+                };
+
+                Ok::<_, BoxError>(response.body(body).unwrap())
+            }
+        });
+
+        (MockServer::new(service), receiver)
+    }
+
+    fn request() -> Request<Body> {
+        Request::builder()
+            .uri("http://localhost/test.Service/Method")
+            .body(Body::new(Full::new(Bytes::from_static(b"request"))))
+            .unwrap()
+    }
+
+    async fn send<S>(service: &mut S) -> Result<Response<Body>, BoxError>
+    where
+        S: Service<Request<Body>, Response = Response<Body>, Error = BoxError>,
+    {
+        service.ready().await?.call(request()).await
+    }
+
+    #[tokio::test]
+    async fn caches_tokens_until_they_expire() {
+        let token_source = CountingTokenSource::new(Some(Duration::from_secs(3600)));
+        let (server, mut authorizations) = mock_server(Vec::new());
+        let mut service = CredentialsLayer::new(token_source.clone(), TransportSecurity::Tls).layer(server);
+
+        send(&mut service).await.unwrap();
+        send(&mut service).await.unwrap();
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-1");
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-1");
+        assert_eq!(token_source.fetches(), 1);
+    }
+
+    #[tokio::test]
+    async fn fetches_new_token_once_expired() {
+        let token_source = CountingTokenSource::new(Some(Duration::ZERO));
+        let (server, mut authorizations) = mock_server(Vec::new());
+        let mut service = CredentialsLayer::new(token_source.clone(), TransportSecurity::Tls).layer(server);
+
+        send(&mut service).await.unwrap();
+        send(&mut service).await.unwrap();
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-1");
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-2");
+        assert_eq!(token_source.fetches(), 2);
+    }
+
+    #[tokio::test]
+    async fn refreshes_tokens_within_refresh_margin_in_background() {
+        let token_source = CountingTokenSource::new(Some(Duration::from_secs(60)));
+        let (server, mut authorizations) = mock_server(Vec::new());
+        let mut service = CredentialsLayer::new(token_source.clone(), TransportSecurity::Tls)
+            .refresh_margin(Duration::from_secs(120))
+            .layer(server);
+
+        // The token is within the refresh margin right away, but is still used while it is refreshed
+        send(&mut service).await.unwrap();
+        send(&mut service).await.unwrap();
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-1");
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-1");
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while token_source.fetches() < 2 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+
+        send(&mut service).await.unwrap();
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-2");
+    }
+
+    #[tokio::test]
+    async fn does_not_refresh_tokens_outside_refresh_margin() {
+        let token_source = CountingTokenSource::new(Some(Duration::from_secs(60)));
+        let (server, _authorizations) = mock_server(Vec::new());
+        let mut service = CredentialsLayer::new(token_source.clone(), TransportSecurity::Tls)
+            .refresh_margin(Duration::from_secs(30))
+            .layer(server);
+
+        send(&mut service).await.unwrap();
+        send(&mut service).await.unwrap();
+        tokio::task::yield_now().await;
+        assert_eq!(token_source.fetches(), 1);
+    }
+
+    #[tokio::test]
+    async fn refuses_plaintext_unless_allowed() {
+        let token_source = CountingTokenSource::new(None);
+        let (server, mut authorizations) = mock_server(Vec::new());
+
+        let mut service =
+            CredentialsLayer::new(token_source.clone(), TransportSecurity::Plaintext).layer(server.clone());
+        assert!(send(&mut service).await.is_err());
+        assert_eq!(token_source.fetches(), 0);
+
+        let mut service = CredentialsLayer::new(token_source.clone(), TransportSecurity::Local).layer(server.clone());
+        send(&mut service).await.unwrap();
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-1");
+
+        let mut service = CredentialsLayer::new(token_source, TransportSecurity::Plaintext)
+            .allow_plaintext(true)
+            .layer(server);
+        send(&mut service).await.unwrap();
+        assert!(authorizations.recv().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn retries_once_with_new_token_on_unauthenticated_headers() {
+        let token_source = CountingTokenSource::new(None);
+        let (server, mut authorizations) = mock_server(vec![Reply::UnauthenticatedHeaders]);
+        let mut service = CredentialsLayer::new(token_source.clone(), TransportSecurity::Tls).layer(server);
+
+        let response = send(&mut service).await.unwrap();
+        assert!(!is_unauthenticated(response.headers()));
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-1");
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-2");
+
+        send(&mut service).await.unwrap();
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-2");
+        assert_eq!(token_source.fetches(), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_only_once_on_unauthenticated_headers() {
+        let token_source = CountingTokenSource::new(None);
+        let (server, mut authorizations) =
+            mock_server(vec![Reply::UnauthenticatedHeaders, Reply::UnauthenticatedHeaders]);
+        let mut service = CredentialsLayer::new(token_source, TransportSecurity::Tls).layer(server);
+
+        let response = send(&mut service).await.unwrap();
+        assert!(is_unauthenticated(response.headers()));
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-1");
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-2");
+        assert!(authorizations.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn invalidates_token_on_unauthenticated_trailers() {
+        let token_source = CountingTokenSource::new(None);
+        let (server, mut authorizations) = mock_server(vec![Reply::UnauthenticatedTrailers]);
+        let mut service = CredentialsLayer::new(token_source.clone(), TransportSecurity::Tls).layer(server);
+
+        let response = send(&mut service).await.unwrap();
+        let trailers = response.into_body().collect().await.unwrap().trailers().cloned();
+        assert!(trailers.as_ref().is_some_and(is_unauthenticated));
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-1");
+
+        send(&mut service).await.unwrap();
+        assert_eq!(authorizations.recv().await.unwrap(), "Bearer token-2");
+        assert_eq!(token_source.fetches(), 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_token_on_successful_trailers() {
+        let token_source = CountingTokenSource::new(None);
+        let (server, _authorizations) = mock_server(Vec::new());
+        let mut service = CredentialsLayer::new(token_source.clone(), TransportSecurity::Tls).layer(server);
+
+        let response = send(&mut service).await.unwrap();
+        response.into_body().collect().await.unwrap();
+        send(&mut service).await.unwrap();
+        assert_eq!(token_source.fetches(), 1);
+    }
+
+    #[cfg(all(feature = "singleton-channel", feature = "unix-transport"))]
+    #[tokio::test]
+    async fn can_be_layered_onto_channels() {
+        let connector = crate::GrpcConnectorBuilder::new().build_to_unix_socket("/nonexistent.sock");
+        let channel = crate::SingletonGrpcChannelBuilder::new(1)
+            .layer(CredentialsLayer::new(
+                CountingTokenSource::new(None),
+                connector.transport_security(),
+            ))
+            .build(connector);
+        drop(channel);
+    }
+}
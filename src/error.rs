@@ -0,0 +1,26 @@
+/// An error emitted when a configuration is invalid, naming the offending field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// The path of the offending field, such as `http2.max_frame_size`.
+    pub field: String,
+    /// A description of what is wrong with the field's value.
+    pub message: String,
+}
+
+impl ConfigError {
+    #[cfg_attr(not(feature = "__channel"), allow(unused))]
+    pub(crate) fn new<F: Into<String>, M: Into<String>>(field: F, message: M) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid configuration of {}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
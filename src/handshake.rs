@@ -0,0 +1,331 @@
+use std::{fmt::Debug, pin::Pin, sync::Arc};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower::BoxError;
+
+use crate::stream::ConnectionInfo;
+
+/// An I/O object a [Handshake] is performed over, implemented for all [Unpin] [Send] types that implement [tokio]'s I/O
+/// traits: [AsyncRead] and [AsyncWrite].
+pub trait HandshakeIo: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<IO> HandshakeIo for IO where IO: AsyncRead + AsyncWrite + Unpin + Send {}
+
+/// A future returned by a [Handshake], yielding either metadata about the handshake or a boxed type-erased
+/// [std::error::Error].
+pub type HandshakeFuture<'a> = Pin<Box<dyn Future<Output = Result<http::Extensions, BoxError>> + Send + 'a>>;
+
+/// A connection preamble exchanged with the server after the transport has connected and before HTTP/2 (and, with the
+/// DNS/TCP/TLS transport, TLS) is negotiated on top of it. Handshakes are configured via [GrpcConnectorBuilder::handshake]
+/// and can be combined with all transports.
+///
+/// The metadata returned by a successful handshake is merged into the [ConnectionInfo] of the resulting [GrpcStream],
+/// with types inserted by later handshakes in a chain replacing those inserted by earlier ones.
+///
+/// [GrpcConnectorBuilder::handshake]: crate::GrpcConnectorBuilder::handshake
+/// [GrpcStream]: crate::GrpcStream
+pub trait Handshake: Send + Sync + 'static {
+    /// Perform the handshake over the given [HandshakeIo], yielding metadata about it.
+    fn handshake<'a>(&'a self, io: &'a mut dyn HandshakeIo) -> HandshakeFuture<'a>;
+}
+
+/// An ordered chain of [Handshake]s performed one after another on a freshly connected transport.
+#[derive(Clone, Default)]
+pub(crate) struct HandshakeChain {
+    handshakes: Vec<Arc<dyn Handshake>>,
+}
+
+impl Debug for HandshakeChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandshakeChain")
+            .field("len", &self.handshakes.len())
+            .finish()
+    }
+}
+
+impl HandshakeChain {
+    pub(crate) fn push(&mut self, handshake: Arc<dyn Handshake>) {
+        self.handshakes.push(handshake);
+    }
+
+    #[cfg_attr(not(feature = "__transport"), allow(unused))]
+    pub(crate) async fn perform(&self, io: &mut dyn HandshakeIo) -> Result<ConnectionInfo, BoxError> {
+        let mut connection_info = ConnectionInfo::default();
+
+        for handshake in &self.handshakes {
+            connection_info.extend(handshake.handshake(io).await?);
+        }
+
+        Ok(connection_info)
+    }
+}
+
+/// A [Handshake] implementing the host side of Firecracker's virtio-vsock multiplexing protocol, asking Firecracker to
+/// establish a tunnel to the given guest port. A successful handshake inserts [FirecrackerConnectionInfo] into the
+/// [ConnectionInfo] of the resulting [GrpcStream](crate::GrpcStream).
+///
+/// The response is read byte-by-byte, so no data past the handshake response (such as the first bytes of HTTP/2 data
+/// sent by the guest) is ever consumed.
+#[cfg(feature = "firecracker-handshake")]
+#[derive(Debug, Clone, Copy)]
+pub struct FirecrackerHandshake {
+    port: u32,
+    timeout: Option<std::time::Duration>,
+}
+
+/// Details of a successful [FirecrackerHandshake], as reported by Firecracker.
+#[cfg(feature = "firecracker-handshake")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirecrackerConnectionInfo {
+    /// The guest port the tunnel was established to.
+    pub guest_port: u32,
+    /// The host-side port Firecracker assigned to the tunnel.
+    pub host_port: u32,
+}
+
+#[cfg(feature = "firecracker-handshake")]
+impl FirecrackerHandshake {
+    /// The maximum length of a well-formed Firecracker response, being `OK ` followed by a [u32] and a newline.
+    const MAX_RESPONSE_LENGTH: usize = 14;
+
+    /// Create a new [FirecrackerHandshake] to the given guest port.
+    pub fn new(port: u32) -> Self {
+        Self { port, timeout: None }
+    }
+
+    /// Set a timeout [Duration](std::time::Duration) for the handshake, separate from the timeout of the entire
+    /// connection attempt configured via [GrpcConnectorBuilder::timeout](crate::GrpcConnectorBuilder::timeout).
+    pub fn timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    async fn perform(&self, io: &mut dyn HandshakeIo) -> Result<FirecrackerConnectionInfo, std::io::Error> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        io.write_all(format!("CONNECT {}\n", self.port).as_bytes()).await?;
+        io.flush().await?;
+
+        let mut response = Vec::with_capacity(Self::MAX_RESPONSE_LENGTH);
+        loop {
+            let mut byte = [0u8];
+            if io.read(&mut byte).await? == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionRefused,
+                    "Firecracker closed the connection instead of establishing a tunnel to the given guest port",
+                ));
+            }
+
+            if byte[0] == b'\n' {
+                break;
+            }
+
+            if response.len() == Self::MAX_RESPONSE_LENGTH {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Firecracker response exceeds the maximum length of a handshake response",
+                ));
+            }
+
+            response.push(byte[0]);
+        }
+
+        let host_port = parse_firecracker_response(&response)?;
+
+        Ok(FirecrackerConnectionInfo {
+            guest_port: self.port,
+            host_port,
+        })
+    }
+}
+
+/// Parse a Firecracker handshake response line (without the trailing newline) into the host port it carries.
+#[cfg(feature = "firecracker-handshake")]
+fn parse_firecracker_response(response: &[u8]) -> Result<u32, std::io::Error> {
+    let Some(host_port) = response.strip_prefix(b"OK ") else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "Firecracker refused to establish a tunnel to the given guest port",
+        ));
+    };
+
+    std::str::from_utf8(host_port)
+        .ok()
+        .filter(|host_port| !host_port.is_empty() && host_port.bytes().all(|byte| byte.is_ascii_digit()))
+        .and_then(|host_port| host_port.parse().ok())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Firecracker response contains a malformed host port",
+            )
+        })
+}
+
+#[cfg(feature = "firecracker-handshake")]
+impl Handshake for FirecrackerHandshake {
+    fn handshake<'a>(&'a self, io: &'a mut dyn HandshakeIo) -> HandshakeFuture<'a> {
+        Box::pin(async move {
+            let connection_info = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, self.perform(io)).await.map_err(|_| {
+                    std::io::Error::new(std::io::ErrorKind::TimedOut, "Firecracker handshake timed out")
+                })??,
+                None => self.perform(io).await?,
+            };
+
+            let mut extensions = http::Extensions::new();
+            extensions.insert(connection_info);
+            Ok(extensions)
+        })
+    }
+}
+
+#[cfg(all(test, feature = "firecracker-handshake"))]
+mod tests {
+    use std::io::ErrorKind;
+
+    use proptest::prelude::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    const HTTP2_PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+    /// Perform a [FirecrackerHandshake] against a peer that replies with the given bytes and then closes its side.
+    async fn perform_against(reply: &[u8]) -> Result<FirecrackerConnectionInfo, std::io::Error> {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        server.write_all(reply).await.unwrap();
+        server.shutdown().await.unwrap();
+        FirecrackerHandshake::new(52).perform(&mut client).await
+    }
+
+    #[test]
+    fn parses_well_formed_response() {
+        assert_eq!(parse_firecracker_response(b"OK 1234").unwrap(), 1234);
+        assert_eq!(parse_firecracker_response(b"OK 0").unwrap(), 0);
+        assert_eq!(parse_firecracker_response(b"OK 4294967295").unwrap(), u32::MAX);
+    }
+
+    #[test]
+    fn rejects_missing_ok_prefix() {
+        for response in [&b""[..], b"OK", b"ok 1234", b"ERR 1234", b" OK 1234"] {
+            let err = parse_firecracker_response(response).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionRefused, "{response:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_host_port() {
+        for response in [
+            &b"OK "[..],
+            b"OK  1234",
+            b"OK 1234 ",
+            b"OK 12a4",
+            b"OK -1",
+            b"OK +1",
+            b"OK 4294967296",
+            b"OK \xff",
+        ] {
+            let err = parse_firecracker_response(response).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData, "{response:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_response() {
+        for reply in [&b""[..], b"OK", b"OK 12"] {
+            let err = perform_against(reply).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::ConnectionRefused, "{reply:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_response() {
+        let err = perform_against(b"OK 12345678901234\n").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // An overlong response is rejected as soon as it exceeds the maximum length, without waiting for a newline
+        let err = perform_against(b"OK 123456789012345").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn rejects_empty_host_port() {
+        let err = perform_against(b"OK \n").await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn does_not_read_past_response() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("firecracker.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 11];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"CONNECT 52\n");
+
+            // Send the response and the first HTTP/2 bytes in a single write, so that they arrive together
+            stream.write_all(&[b"OK 1234\n", HTTP2_PREFACE].concat()).await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        let extensions = FirecrackerHandshake::new(52).handshake(&mut stream).await.unwrap();
+        assert_eq!(
+            extensions.get::<FirecrackerConnectionInfo>(),
+            Some(&FirecrackerConnectionInfo {
+                guest_port: 52,
+                host_port: 1234,
+            })
+        );
+
+        let mut remaining = Vec::new();
+        stream.read_to_end(&mut remaining).await.unwrap();
+        assert_eq!(remaining, HTTP2_PREFACE);
+        server.await.unwrap();
+    }
+
+    proptest! {
+        #[test]
+        fn parser_never_panics(response in proptest::collection::vec(any::<u8>(), 0..32)) {
+            let _ = parse_firecracker_response(&response);
+        }
+
+        #[test]
+        fn parser_accepts_only_canonical_ports(response in proptest::collection::vec(any::<u8>(), 0..32)) {
+            if let Ok(host_port) = parse_firecracker_response(&response) {
+                let digits = &response[3..];
+                prop_assert!(response.starts_with(b"OK "));
+                prop_assert!(digits.iter().all(u8::is_ascii_digit));
+                prop_assert_eq!(std::str::from_utf8(digits).unwrap().parse::<u32>().unwrap(), host_port);
+            }
+        }
+
+        #[test]
+        fn parser_round_trips_ports(host_port in any::<u32>()) {
+            let response = format!("OK {host_port}");
+            prop_assert_eq!(parse_firecracker_response(response.as_bytes()).unwrap(), host_port);
+        }
+
+        #[test]
+        fn handshake_round_trips_ports(host_port in any::<u32>(), trailing in proptest::collection::vec(any::<u8>(), 0..16)) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            let (connection_info, remaining) = runtime.block_on(async {
+                let (mut client, mut server) = tokio::io::duplex(64);
+                server.write_all(format!("OK {host_port}\n").as_bytes()).await.unwrap();
+                server.write_all(&trailing).await.unwrap();
+                server.shutdown().await.unwrap();
+
+                let connection_info = FirecrackerHandshake::new(52).perform(&mut client).await.unwrap();
+                let mut remaining = Vec::new();
+                client.read_to_end(&mut remaining).await.unwrap();
+                (connection_info, remaining)
+            });
+
+            prop_assert_eq!(connection_info.host_port, host_port);
+            prop_assert_eq!(remaining, trailing);
+        }
+    }
+}
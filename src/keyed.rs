@@ -0,0 +1,96 @@
+use std::{fmt::Debug, path::PathBuf, sync::Arc};
+
+use tower::BoxError;
+
+use crate::{GrpcConnector, GrpcConnectorBuilder};
+
+/// A routing key identifying the target of a gRPC request, such as the ID of a microVM. A [GrpcRoutingKey] is inserted
+/// into the extensions of a request to route it via a keyed channel.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct GrpcRoutingKey(pub String);
+
+impl<S: Into<String>> From<S> for GrpcRoutingKey {
+    fn from(value: S) -> Self {
+        Self(value.into())
+    }
+}
+
+/// The Unix socket a [GrpcRoutingKey] maps to, optionally combined with a guest port to perform a Firecracker
+/// handshake to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixSocketRoute {
+    pub socket_path: PathBuf,
+    pub guest_port: Option<u32>,
+}
+
+type Router = dyn Fn(&GrpcRoutingKey) -> Result<UnixSocketRoute, BoxError> + Send + Sync;
+
+/// A factory of [GrpcConnector]s to Unix sockets, mapping each [GrpcRoutingKey] to a [UnixSocketRoute] and building a
+/// [GrpcConnector] to it from a shared [GrpcConnectorBuilder]. This struct is cheaply [Clone]-able.
+#[derive(Clone)]
+pub struct KeyedGrpcConnectorFactory {
+    connector_builder: GrpcConnectorBuilder,
+    router: Arc<Router>,
+}
+
+impl Debug for KeyedGrpcConnectorFactory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyedGrpcConnectorFactory")
+            .field("connector_builder", &self.connector_builder)
+            .finish_non_exhaustive()
+    }
+}
+
+impl KeyedGrpcConnectorFactory {
+    /// The placeholder substituted with the [GrpcRoutingKey] in a socket path template.
+    pub const KEY_PLACEHOLDER: &str = "{key}";
+
+    /// Create a new [KeyedGrpcConnectorFactory] from a [GrpcConnectorBuilder] and a function mapping a [GrpcRoutingKey]
+    /// to a [UnixSocketRoute] or a boxed type-erased [std::error::Error].
+    pub fn new<F>(connector_builder: GrpcConnectorBuilder, router: F) -> Self
+    where
+        F: Fn(&GrpcRoutingKey) -> Result<UnixSocketRoute, BoxError> + Send + Sync + 'static,
+    {
+        Self {
+            connector_builder,
+            router: Arc::new(router),
+        }
+    }
+
+    /// Create a new [KeyedGrpcConnectorFactory] from a [GrpcConnectorBuilder] and a socket path template, such as
+    /// `/run/fc/{key}/v.sock`, with every occurrence of [Self::KEY_PLACEHOLDER] substituted with the [GrpcRoutingKey].
+    /// A Firecracker handshake to the given guest port is performed on every connection. Keys that are empty, contain
+    /// a path separator or a NUL byte, or consist of `.` or `..` are rejected.
+    pub fn from_template<P: Into<String>>(
+        connector_builder: GrpcConnectorBuilder,
+        path_template: P,
+        guest_port: u32,
+    ) -> Self {
+        let path_template = path_template.into();
+
+        Self::new(connector_builder, move |key| {
+            let GrpcRoutingKey(key) = key;
+
+            if key.is_empty() || key == "." || key == ".." || key.contains(['/', '\0']) {
+                return Err(format!("The routing key {key:?} cannot be substituted into a socket path").into());
+            }
+
+            Ok(UnixSocketRoute {
+                socket_path: PathBuf::from(path_template.replace(Self::KEY_PLACEHOLDER, key)),
+                guest_port: Some(guest_port),
+            })
+        })
+    }
+
+    /// Build a [GrpcConnector] to the [UnixSocketRoute] the given [GrpcRoutingKey] maps to.
+    pub fn build(&self, key: &GrpcRoutingKey) -> Result<GrpcConnector, BoxError> {
+        let route = (self.router)(key)?;
+        let mut connector_builder = self.connector_builder.clone();
+
+        if let Some(guest_port) = route.guest_port {
+            connector_builder = connector_builder.perform_firecracker_handshake(guest_port);
+        }
+
+        Ok(connector_builder.build_to_unix_socket(route.socket_path))
+    }
+}
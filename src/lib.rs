@@ -1,12 +1,22 @@
 #[cfg(feature = "__channel")]
 mod channel;
+#[cfg(all(feature = "serde", feature = "__channel"))]
+mod config;
 mod connector;
+mod error;
+mod handshake;
 mod stream;
+mod target;
 
 #[cfg(feature = "__channel")]
 pub use channel::*;
+#[cfg(all(feature = "serde", feature = "__channel"))]
+pub use config::*;
 pub use connector::*;
-pub use stream::GrpcStream;
+pub use error::ConfigError;
+pub use handshake::*;
+pub use stream::{ConnectionInfo, GrpcStream};
+pub use target::*;
 
 #[cfg(any(feature = "dns-tcp-transport", feature = "dns-tcp-tls-transport"))]
 mod dns;
@@ -18,10 +28,36 @@ mod tcp;
 #[cfg(any(feature = "dns-tcp-transport", feature = "dns-tcp-tls-transport"))]
 pub use tcp::*;
 
+#[cfg(feature = "keyed-channel")]
+mod keyed;
+#[cfg(feature = "keyed-channel")]
+pub use keyed::*;
+
+#[cfg(feature = "vsock-transport")]
+mod vsock;
+#[cfg(feature = "vsock-transport")]
+pub use vsock::*;
+
 #[cfg(feature = "dns-tcp-tls-transport")]
 mod tls;
 #[cfg(feature = "dns-tcp-tls-transport")]
 pub use tls::*;
 
+#[cfg(feature = "per-rpc-credentials")]
+mod credentials;
+#[cfg(feature = "per-rpc-credentials")]
+pub use credentials::*;
+
+#[cfg(feature = "oauth2-token-source")]
+mod oauth2;
+#[cfg(feature = "oauth2-token-source")]
+pub use oauth2::*;
+
+#[cfg(any(feature = "per-rpc-credentials", feature = "__channel"))]
+mod replay;
+
+#[cfg(test)]
+mod test_util;
+
 type BoxResultFuture<O> =
     std::pin::Pin<Box<dyn Future<Output = Result<O, Box<dyn std::error::Error + Send + Sync>>> + Send + 'static>>;
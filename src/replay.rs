@@ -0,0 +1,122 @@
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::{Bytes, BytesMut};
+use http::{HeaderMap, Method, Request, Uri, Version};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use tonic::body::Body;
+
+/// The body of a request being recorded while it is sent, so that the request can be replayed afterwards.
+#[derive(Debug, Default)]
+struct Recording {
+    data: BytesMut,
+    complete: bool,
+    discarded: bool,
+}
+
+/// A request body wrapper that records the data frames of the wrapped body as they are polled, up to a size limit.
+struct RecordingBody {
+    inner: Body,
+    recording: Arc<Mutex<Recording>>,
+    limit: usize,
+}
+
+impl HttpBody for RecordingBody {
+    type Data = Bytes;
+
+    type Error = tonic::Status;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let poll = Pin::new(&mut self.inner).poll_frame(cx);
+        let mut recording = self.recording.lock().expect("request recording mutex was poisoned");
+
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => match frame.data_ref() {
+                Some(data) if !recording.discarded && recording.data.len() + data.len() <= self.limit => {
+                    recording.data.extend_from_slice(data);
+                }
+                _ => {
+                    recording.discarded = true;
+                    recording.data = BytesMut::new();
+                }
+            },
+            Poll::Ready(Some(Err(_))) => recording.discarded = true,
+            Poll::Ready(None) => recording.complete = true,
+            Poll::Pending => {}
+        }
+
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Everything needed to replay a request recorded via [record], available once its body was sent completely.
+pub(crate) struct RequestReplay {
+    method: Method,
+    uri: Uri,
+    version: Version,
+    headers: HeaderMap,
+    extensions: http::Extensions,
+    recording: Arc<Mutex<Recording>>,
+}
+
+/// Wrap the body of the given request so that it is recorded (up to the given size limit) as it is sent, returning
+/// the wrapped request and a [RequestReplay] for it.
+pub(crate) fn record(request: Request<Body>, limit: usize) -> (Request<Body>, RequestReplay) {
+    let (parts, body) = request.into_parts();
+    let recording = Arc::new(Mutex::new(Recording {
+        complete: body.is_end_stream(),
+        ..Default::default()
+    }));
+
+    let replay = RequestReplay {
+        method: parts.method.clone(),
+        uri: parts.uri.clone(),
+        version: parts.version,
+        headers: parts.headers.clone(),
+        extensions: parts.extensions.clone(),
+        recording: recording.clone(),
+    };
+
+    let request = Request::from_parts(
+        parts,
+        Body::new(RecordingBody {
+            inner: body,
+            recording,
+            limit,
+        }),
+    );
+
+    (request, replay)
+}
+
+impl RequestReplay {
+    /// Rebuild the recorded request, if its body was sent completely and fit into the size limit.
+    pub(crate) fn replay(self) -> Option<Request<Body>> {
+        let recording = self.recording.lock().expect("request recording mutex was poisoned");
+        if !recording.complete || recording.discarded {
+            return None;
+        }
+
+        let mut request = Request::new(Body::new(http_body_util::Full::new(recording.data.clone().freeze())));
+        *request.method_mut() = self.method;
+        *request.uri_mut() = self.uri;
+        *request.version_mut() = self.version;
+        *request.headers_mut() = self.headers;
+        *request.extensions_mut() = self.extensions;
+        Some(request)
+    }
+}
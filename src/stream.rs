@@ -21,13 +21,45 @@ impl<S> HyperIo for S where S: Read + Write + Unpin + Send {}
 pub struct GrpcStream {
     #[cfg_attr(not(feature = "__transport"), allow(unused))]
     pub(crate) inner: GrpcStreamInner,
+    pub(crate) info: ConnectionInfo,
+}
+
+/// Metadata about an established [GrpcStream], such as the metadata yielded by [Handshake]s performed when connecting
+/// and the negotiated TLS details. This struct is a type map that is cheaply [Clone]-able. Both gRPC channels insert
+/// the [ConnectionInfo] of the connection a request was sent over into the extensions of its response.
+///
+/// [Handshake]: crate::Handshake
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    extensions: http::Extensions,
+}
+
+impl ConnectionInfo {
+    /// Get a reference to a value of the given type contained within this [ConnectionInfo], if it is present.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get()
+    }
+
+    /// Insert a value of the given type into this [ConnectionInfo], returning the previously present value of this type.
+    pub fn insert<T: Clone + Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.extensions.insert(value)
+    }
+
+    pub(crate) fn extend(&mut self, extensions: http::Extensions) {
+        self.extensions.extend(extensions);
+    }
+
+    #[cfg(feature = "custom-transport")]
+    pub(crate) fn extend_from(&mut self, other: ConnectionInfo) {
+        self.extensions.extend(other.extensions);
+    }
 }
 
 pub(crate) enum GrpcStreamInner {
-    #[cfg(feature = "dns-tcp-transport")]
+    #[cfg(any(feature = "dns-tcp-transport", feature = "dns-tcp-tls-transport"))]
     DnsTcp(hyper_util::rt::TokioIo<tokio::net::TcpStream>),
     #[cfg(feature = "dns-tcp-tls-transport")]
-    DnsTcpTls(hyper_rustls::MaybeHttpsStream<hyper_util::rt::TokioIo<tokio::net::TcpStream>>),
+    DnsTcpTls(Box<hyper_util::rt::TokioIo<tokio_rustls::client::TlsStream<tokio::net::TcpStream>>>),
     #[cfg(feature = "unix-transport")]
     Unix(hyper_util::rt::tokio::WithHyperIo<tokio::net::UnixStream>),
     #[cfg(feature = "vsock-transport")]
@@ -43,6 +75,7 @@ impl GrpcStream {
     pub fn wrap_hyper_io<IO: Read + Write + Unpin + Send + 'static>(io: IO) -> Self {
         Self {
             inner: GrpcStreamInner::Custom(Box::new(io)),
+            info: ConnectionInfo::default(),
         }
     }
 
@@ -53,13 +86,15 @@ impl GrpcStream {
         Self::wrap_hyper_io(hyper_util::rt::tokio::WithHyperIo::new(io))
     }
 
-    #[cfg(feature = "dns-tcp-tls-transport")]
-    pub(crate) fn dns_tcp_tls(
-        stream: hyper_rustls::MaybeHttpsStream<hyper_util::rt::TokioIo<tokio::net::TcpStream>>,
-    ) -> Self {
-        Self {
-            inner: GrpcStreamInner::DnsTcpTls(stream),
-        }
+    /// Get the [ConnectionInfo] of this [GrpcStream].
+    pub fn connection_info(&self) -> &ConnectionInfo {
+        &self.info
+    }
+
+    /// Get a mutable reference to the [ConnectionInfo] of this [GrpcStream], which can be used by custom transports to
+    /// attach their own metadata.
+    pub fn connection_info_mut(&mut self) -> &mut ConnectionInfo {
+        &mut self.info
     }
 }
 
@@ -74,7 +109,7 @@ impl Read for GrpcStream {
 
         #[cfg(feature = "__transport")]
         match &mut self.get_mut().inner {
-            #[cfg(feature = "dns-tcp-transport")]
+            #[cfg(any(feature = "dns-tcp-transport", feature = "dns-tcp-tls-transport"))]
             GrpcStreamInner::DnsTcp(stream) => Pin::new(stream).poll_read(cx, buf),
             #[cfg(feature = "dns-tcp-tls-transport")]
             GrpcStreamInner::DnsTcpTls(stream) => Pin::new(stream).poll_read(cx, buf),
@@ -99,7 +134,7 @@ impl Write for GrpcStream {
 
         #[cfg(feature = "__transport")]
         match &mut self.get_mut().inner {
-            #[cfg(feature = "dns-tcp-transport")]
+            #[cfg(any(feature = "dns-tcp-transport", feature = "dns-tcp-tls-transport"))]
             GrpcStreamInner::DnsTcp(stream) => Pin::new(stream).poll_write(cx, buf),
             #[cfg(feature = "dns-tcp-tls-transport")]
             GrpcStreamInner::DnsTcpTls(stream) => Pin::new(stream).poll_write(cx, buf),
@@ -121,7 +156,7 @@ impl Write for GrpcStream {
 
         #[cfg(feature = "__transport")]
         match &mut self.get_mut().inner {
-            #[cfg(feature = "dns-tcp-transport")]
+            #[cfg(any(feature = "dns-tcp-transport", feature = "dns-tcp-tls-transport"))]
             GrpcStreamInner::DnsTcp(stream) => Pin::new(stream).poll_flush(cx),
             #[cfg(feature = "dns-tcp-tls-transport")]
             GrpcStreamInner::DnsTcpTls(stream) => Pin::new(stream).poll_flush(cx),
@@ -143,7 +178,7 @@ impl Write for GrpcStream {
 
         #[cfg(feature = "__transport")]
         match &mut self.get_mut().inner {
-            #[cfg(feature = "dns-tcp-transport")]
+            #[cfg(any(feature = "dns-tcp-transport", feature = "dns-tcp-tls-transport"))]
             GrpcStreamInner::DnsTcp(stream) => Pin::new(stream).poll_shutdown(cx),
             #[cfg(feature = "dns-tcp-tls-transport")]
             GrpcStreamInner::DnsTcpTls(stream) => Pin::new(stream).poll_shutdown(cx),
@@ -160,6 +195,17 @@ impl Write for GrpcStream {
 #[cfg(feature = "pooled-channel")]
 impl Connection for GrpcStream {
     fn connected(&self) -> Connected {
-        Connected::new()
+        let connected = Connected::new().extra(self.info.clone());
+
+        #[cfg(feature = "dns-tcp-tls-transport")]
+        if self
+            .info
+            .get::<crate::tls::TlsConnectionInfo>()
+            .is_some_and(|tls_info| tls_info.alpn_protocol.as_deref() == Some(b"h2"))
+        {
+            return connected.negotiated_h2();
+        }
+
+        connected
     }
 }
@@ -0,0 +1,500 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use crate::{GrpcConnector, GrpcConnectorBuilder};
+
+/// The port used for `dns:`, `ipv4:` and `ipv6:` targets that don't specify one, as defined by gRPC's naming scheme.
+pub const DEFAULT_TARGET_PORT: u16 = 443;
+
+/// A gRPC target parsed from a target string according to gRPC's naming scheme, extended with a `firecracker:` scheme.
+/// Target strings without a known scheme, such as `localhost:50051`, are interpreted as `dns:` targets.
+///
+/// The supported forms are `dns:[//authority/]host[:port]` (only an empty authority is supported),
+/// `ipv4:address[:port][,address[:port],...]`, `ipv6:address|[address]:port[,...]`, `unix:path`, `unix:///absolute-path`,
+/// `unix-abstract:name`, `vsock:cid:port` and `firecracker:path:guest-port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrpcTarget {
+    /// A hostname resolved via DNS, connected to over TCP.
+    Dns { host: String, port: u16 },
+    /// One or multiple IP addresses, connected to over TCP in order.
+    Ip(Vec<SocketAddr>),
+    /// A Unix socket at a filesystem path.
+    Unix(PathBuf),
+    /// A Unix socket in Linux's abstract namespace.
+    UnixAbstract(String),
+    /// A virtio-vsock socket.
+    Vsock { cid: u32, port: u32 },
+    /// A Unix socket of a Firecracker microVM's virtio-vsock device, on which a Firecracker handshake to the given guest
+    /// port is performed.
+    Firecracker { socket_path: PathBuf, guest_port: u32 },
+}
+
+/// An error emitted when parsing a [GrpcTarget] or building a [GrpcConnector] to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrpcTargetError {
+    /// The target has a known scheme but no address, such as `unix:`.
+    MissingAddress { scheme: &'static str },
+    /// A `dns:` target specifies the authority of a DNS server, which isn't supported.
+    UnsupportedDnsAuthority(String),
+    /// A host or IP address in the target is invalid.
+    InvalidAddress(String),
+    /// A port in the target is invalid.
+    InvalidPort(String),
+    /// The CID of a `vsock:` target is invalid.
+    InvalidCid(String),
+    /// The transport required by the target's scheme was not enabled via the given feature of this crate.
+    TransportNotEnabled {
+        scheme: &'static str,
+        feature: &'static str,
+    },
+}
+
+impl std::fmt::Display for GrpcTargetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GrpcTargetError::MissingAddress { scheme } => write!(f, "The {scheme}: target has no address"),
+            GrpcTargetError::UnsupportedDnsAuthority(authority) => {
+                write!(f, "The DNS authority {authority:?} is not supported in a dns: target")
+            }
+            GrpcTargetError::InvalidAddress(address) => write!(f, "The address {address:?} in the target is invalid"),
+            GrpcTargetError::InvalidPort(port) => write!(f, "The port {port:?} in the target is invalid"),
+            GrpcTargetError::InvalidCid(cid) => write!(f, "The CID {cid:?} in the vsock: target is invalid"),
+            GrpcTargetError::TransportNotEnabled { scheme, feature } => write!(
+                f,
+                "The {scheme}: target requires the {feature} feature of alternate-tonic-client to be enabled"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GrpcTargetError {}
+
+fn parse_port<T: FromStr>(port: &str) -> Result<T, GrpcTargetError> {
+    port.parse().map_err(|_| GrpcTargetError::InvalidPort(port.to_owned()))
+}
+
+fn parse_dns_endpoint(endpoint: &str) -> Result<GrpcTarget, GrpcTargetError> {
+    let (host, port) = match endpoint.strip_prefix('[') {
+        Some(bracketed) => match bracketed.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => match port.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(GrpcTargetError::InvalidAddress(endpoint.to_owned())),
+            },
+            None => return Err(GrpcTargetError::InvalidAddress(endpoint.to_owned())),
+        },
+        None => match endpoint.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (endpoint, None),
+        },
+    };
+
+    if host.is_empty() || host.contains(|c: char| c.is_whitespace() || c == '/' || c == '@') {
+        return Err(GrpcTargetError::InvalidAddress(host.to_owned()));
+    }
+
+    Ok(GrpcTarget::Dns {
+        host: host.to_owned(),
+        port: port.map(parse_port).transpose()?.unwrap_or(DEFAULT_TARGET_PORT),
+    })
+}
+
+fn parse_ipv4_address(address: &str) -> Result<SocketAddr, GrpcTargetError> {
+    let (ip, port) = match address.split_once(':') {
+        Some((ip, port)) => (ip, parse_port(port)?),
+        None => (address, DEFAULT_TARGET_PORT),
+    };
+
+    let ip = Ipv4Addr::from_str(ip).map_err(|_| GrpcTargetError::InvalidAddress(ip.to_owned()))?;
+    Ok(SocketAddr::new(ip.into(), port))
+}
+
+fn parse_ipv6_address(address: &str) -> Result<SocketAddr, GrpcTargetError> {
+    let (ip, port) = match address.strip_prefix('[').and_then(|address| address.split_once("]:")) {
+        Some((ip, port)) => (ip, parse_port(port)?),
+        None => (address, DEFAULT_TARGET_PORT),
+    };
+
+    let ip = Ipv6Addr::from_str(ip).map_err(|_| GrpcTargetError::InvalidAddress(ip.to_owned()))?;
+    Ok(SocketAddr::new(ip.into(), port))
+}
+
+impl FromStr for GrpcTarget {
+    type Err = GrpcTargetError;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, rest)) = target.split_once(':') else {
+            return parse_dns_endpoint(target);
+        };
+
+        let scheme: &'static str = match scheme {
+            "dns" => "dns",
+            "ipv4" => "ipv4",
+            "ipv6" => "ipv6",
+            "unix" => "unix",
+            "unix-abstract" => "unix-abstract",
+            "vsock" => "vsock",
+            "firecracker" => "firecracker",
+            _ => return parse_dns_endpoint(target),
+        };
+
+        if rest.is_empty() {
+            return Err(GrpcTargetError::MissingAddress { scheme });
+        }
+
+        match scheme {
+            "dns" => match rest.strip_prefix("//") {
+                Some(rest) => match rest.split_once('/') {
+                    Some(("", endpoint)) => parse_dns_endpoint(endpoint),
+                    Some((authority, _)) => Err(GrpcTargetError::UnsupportedDnsAuthority(authority.to_owned())),
+                    None => Err(GrpcTargetError::MissingAddress { scheme }),
+                },
+                None => parse_dns_endpoint(rest),
+            },
+            "ipv4" => Ok(GrpcTarget::Ip(
+                rest.split(',').map(parse_ipv4_address).collect::<Result<_, _>>()?,
+            )),
+            "ipv6" => Ok(GrpcTarget::Ip(
+                rest.split(',').map(parse_ipv6_address).collect::<Result<_, _>>()?,
+            )),
+            "unix" => match rest.strip_prefix("//") {
+                Some(path) if path.starts_with('/') => Ok(GrpcTarget::Unix(PathBuf::from(path))),
+                Some(_) => Err(GrpcTargetError::InvalidAddress(rest.to_owned())),
+                None => Ok(GrpcTarget::Unix(PathBuf::from(rest))),
+            },
+            "unix-abstract" => Ok(GrpcTarget::UnixAbstract(rest.to_owned())),
+            "vsock" => {
+                let (cid, port) = rest
+                    .split_once(':')
+                    .ok_or_else(|| GrpcTargetError::InvalidAddress(rest.to_owned()))?;
+
+                Ok(GrpcTarget::Vsock {
+                    cid: cid.parse().map_err(|_| GrpcTargetError::InvalidCid(cid.to_owned()))?,
+                    port: parse_port(port)?,
+                })
+            }
+            _ => {
+                let (socket_path, guest_port) = rest
+                    .rsplit_once(':')
+                    .ok_or_else(|| GrpcTargetError::InvalidAddress(rest.to_owned()))?;
+
+                if socket_path.is_empty() {
+                    return Err(GrpcTargetError::MissingAddress { scheme });
+                }
+
+                Ok(GrpcTarget::Firecracker {
+                    socket_path: PathBuf::from(socket_path),
+                    guest_port: parse_port(guest_port)?,
+                })
+            }
+        }
+    }
+}
+
+/// Build the [http::Uri] given to the DNS/TCP transports for a [GrpcTarget::Dns].
+#[cfg(any(feature = "dns-tcp-transport", feature = "dns-tcp-tls-transport"))]
+#[cfg_attr(
+    not(any(feature = "dns-tcp-transport", all(feature = "serde", feature = "__channel"))),
+    allow(unused)
+)]
+pub(crate) fn dns_target_uri(host: &str, port: u16) -> Result<http::Uri, GrpcTargetError> {
+    let authority = match host.contains(':') {
+        true => format!("[{host}]:{port}"),
+        false => format!("{host}:{port}"),
+    };
+
+    http::Uri::try_from(format!("http://{authority}")).map_err(|_| GrpcTargetError::InvalidAddress(host.to_owned()))
+}
+
+/// Build the [http::Uri] given to the DNS/TCP transports for a [GrpcTarget::Ip], which has no port, so that the ports
+/// of the addresses yielded by its [static_dns_resolver] are kept.
+#[cfg(feature = "dns-tcp-transport")]
+pub(crate) fn ip_target_uri() -> http::Uri {
+    http::Uri::from_static("http://ip-addresses")
+}
+
+/// Build a [crate::DnsResolver] resolving any name to the addresses of a [GrpcTarget::Ip].
+#[cfg(feature = "dns-tcp-transport")]
+pub(crate) fn static_dns_resolver(addresses: &[SocketAddr]) -> crate::DnsResolver {
+    let addresses = std::sync::Arc::<[SocketAddr]>::from(addresses);
+
+    crate::DnsResolver::new(tower::service_fn(move |_: String| {
+        let addresses = addresses.clone();
+        async move { Ok::<_, std::convert::Infallible>((0..addresses.len()).map(move |i| addresses[i])) }
+    }))
+}
+
+impl GrpcConnectorBuilder {
+    /// Build a [GrpcConnector] to the given [GrpcTarget] using the transport its scheme maps to. `dns:`, `ipv4:` and
+    /// `ipv6:` targets are connected to over TCP without TLS, with the default [crate::DnsResolver] and
+    /// [crate::TcpConfig], while `firecracker:` targets append a [crate::FirecrackerHandshake] to this self.
+    pub fn build_to_target(self, target: &GrpcTarget) -> Result<GrpcConnector, GrpcTargetError> {
+        match target {
+            #[cfg(feature = "dns-tcp-transport")]
+            GrpcTarget::Dns { host, port } => Ok(self.build_to_tcp_host(
+                dns_target_uri(host, *port)?,
+                crate::DnsResolver::default(),
+                crate::TcpConfig::default(),
+            )),
+            #[cfg(feature = "dns-tcp-transport")]
+            GrpcTarget::Ip(addresses) => Ok(self.build_to_tcp_host(
+                ip_target_uri(),
+                static_dns_resolver(addresses),
+                crate::TcpConfig::default(),
+            )),
+            #[cfg(not(feature = "dns-tcp-transport"))]
+            GrpcTarget::Dns { .. } | GrpcTarget::Ip(_) => Err(GrpcTargetError::TransportNotEnabled {
+                scheme: "dns",
+                feature: "dns-tcp-transport",
+            }),
+            #[cfg(feature = "unix-transport")]
+            GrpcTarget::Unix(socket_path) => Ok(self.build_to_unix_socket(socket_path.clone())),
+            #[cfg(feature = "unix-transport")]
+            GrpcTarget::UnixAbstract(name) => Ok(self.build_to_unix_socket(format!("\0{name}"))),
+            #[cfg(not(feature = "unix-transport"))]
+            GrpcTarget::Unix(_) | GrpcTarget::UnixAbstract(_) => Err(GrpcTargetError::TransportNotEnabled {
+                scheme: "unix",
+                feature: "unix-transport",
+            }),
+            #[cfg(feature = "vsock-transport")]
+            GrpcTarget::Vsock { cid, port } => Ok(self.build_to_vsock_socket(*cid, *port)),
+            #[cfg(not(feature = "vsock-transport"))]
+            GrpcTarget::Vsock { .. } => Err(GrpcTargetError::TransportNotEnabled {
+                scheme: "vsock",
+                feature: "vsock-transport",
+            }),
+            #[cfg(all(feature = "unix-transport", feature = "firecracker-handshake"))]
+            GrpcTarget::Firecracker {
+                socket_path,
+                guest_port,
+            } => Ok(self
+                .perform_firecracker_handshake(*guest_port)
+                .build_to_unix_socket(socket_path.clone())),
+            #[cfg(not(all(feature = "unix-transport", feature = "firecracker-handshake")))]
+            GrpcTarget::Firecracker { .. } => Err(GrpcTargetError::TransportNotEnabled {
+                scheme: "firecracker",
+                feature: "unix-transport and firecracker-handshake",
+            }),
+        }
+    }
+}
+
+impl GrpcConnector {
+    /// Parse the given gRPC target string into a [GrpcTarget] and build a [GrpcConnector] to it with a default
+    /// [GrpcConnectorBuilder], as described by [GrpcConnectorBuilder::build_to_target].
+    pub fn from_target(target: &str) -> Result<Self, GrpcTargetError> {
+        GrpcConnectorBuilder::new().build_to_target(&target.parse()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn socket_addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    fn dns(host: &str, port: u16) -> GrpcTarget {
+        GrpcTarget::Dns {
+            host: host.to_owned(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parses_valid_targets() {
+        let cases = [
+            ("localhost", dns("localhost", DEFAULT_TARGET_PORT)),
+            ("localhost:50051", dns("localhost", 50051)),
+            ("example.com:50051", dns("example.com", 50051)),
+            ("[::1]:50051", dns("::1", 50051)),
+            ("[::1]", dns("::1", DEFAULT_TARGET_PORT)),
+            ("dns:localhost", dns("localhost", DEFAULT_TARGET_PORT)),
+            ("dns:localhost:50051", dns("localhost", 50051)),
+            ("dns:///localhost:50051", dns("localhost", 50051)),
+            ("dns:///[::1]:50051", dns("::1", 50051)),
+            ("ipv4:127.0.0.1", GrpcTarget::Ip(vec![socket_addr("127.0.0.1:443")])),
+            (
+                "ipv4:127.0.0.1:50051,10.0.0.1:50052",
+                GrpcTarget::Ip(vec![socket_addr("127.0.0.1:50051"), socket_addr("10.0.0.1:50052")]),
+            ),
+            ("ipv6:::1", GrpcTarget::Ip(vec![socket_addr("[::1]:443")])),
+            (
+                "ipv6:[::1]:50051,[fe80::1]:50052",
+                GrpcTarget::Ip(vec![socket_addr("[::1]:50051"), socket_addr("[fe80::1]:50052")]),
+            ),
+            (
+                "unix:relative/grpc.sock",
+                GrpcTarget::Unix(PathBuf::from("relative/grpc.sock")),
+            ),
+            ("unix:/run/grpc.sock", GrpcTarget::Unix(PathBuf::from("/run/grpc.sock"))),
+            (
+                "unix:///run/grpc.sock",
+                GrpcTarget::Unix(PathBuf::from("/run/grpc.sock")),
+            ),
+            ("unix-abstract:grpc", GrpcTarget::UnixAbstract("grpc".to_owned())),
+            ("vsock:3:50051", GrpcTarget::Vsock { cid: 3, port: 50051 }),
+            (
+                "firecracker:/run/firecracker/v.sock:52",
+                GrpcTarget::Firecracker {
+                    socket_path: PathBuf::from("/run/firecracker/v.sock"),
+                    guest_port: 52,
+                },
+            ),
+        ];
+
+        for (target, expected) in cases {
+            assert_eq!(target.parse::<GrpcTarget>(), Ok(expected), "{target}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_targets() {
+        let cases = [
+            ("dns:", GrpcTargetError::MissingAddress { scheme: "dns" }),
+            ("dns://", GrpcTargetError::MissingAddress { scheme: "dns" }),
+            ("ipv4:", GrpcTargetError::MissingAddress { scheme: "ipv4" }),
+            ("ipv6:", GrpcTargetError::MissingAddress { scheme: "ipv6" }),
+            ("unix:", GrpcTargetError::MissingAddress { scheme: "unix" }),
+            (
+                "unix-abstract:",
+                GrpcTargetError::MissingAddress {
+                    scheme: "unix-abstract",
+                },
+            ),
+            ("vsock:", GrpcTargetError::MissingAddress { scheme: "vsock" }),
+            (
+                "firecracker:",
+                GrpcTargetError::MissingAddress { scheme: "firecracker" },
+            ),
+            (
+                "firecracker::52",
+                GrpcTargetError::MissingAddress { scheme: "firecracker" },
+            ),
+            (
+                "dns://8.8.8.8/localhost:50051",
+                GrpcTargetError::UnsupportedDnsAuthority("8.8.8.8".to_owned()),
+            ),
+            ("dns:///", GrpcTargetError::InvalidAddress(String::new())),
+            (":50051", GrpcTargetError::InvalidAddress(String::new())),
+            (
+                "local host:50051",
+                GrpcTargetError::InvalidAddress("local host".to_owned()),
+            ),
+            ("[::1", GrpcTargetError::InvalidAddress("[::1".to_owned())),
+            ("[::1]50051", GrpcTargetError::InvalidAddress("[::1]50051".to_owned())),
+            (
+                "ipv4:localhost",
+                GrpcTargetError::InvalidAddress("localhost".to_owned()),
+            ),
+            (
+                "ipv4:127.0.0.1,10.0.0",
+                GrpcTargetError::InvalidAddress("10.0.0".to_owned()),
+            ),
+            (
+                "ipv6:127.0.0.1",
+                GrpcTargetError::InvalidAddress("127.0.0.1".to_owned()),
+            ),
+            (
+                "unix://relative.sock",
+                GrpcTargetError::InvalidAddress("//relative.sock".to_owned()),
+            ),
+            ("vsock:3", GrpcTargetError::InvalidAddress("3".to_owned())),
+            (
+                "firecracker:/run/v.sock",
+                GrpcTargetError::InvalidAddress("/run/v.sock".to_owned()),
+            ),
+            ("localhost:http", GrpcTargetError::InvalidPort("http".to_owned())),
+            ("localhost:65536", GrpcTargetError::InvalidPort("65536".to_owned())),
+            ("dns:localhost:", GrpcTargetError::InvalidPort(String::new())),
+            ("ipv4:127.0.0.1:-1", GrpcTargetError::InvalidPort("-1".to_owned())),
+            ("ipv6:[::1]:port", GrpcTargetError::InvalidPort("port".to_owned())),
+            ("vsock:3:port", GrpcTargetError::InvalidPort("port".to_owned())),
+            (
+                "firecracker:/run/v.sock:port",
+                GrpcTargetError::InvalidPort("port".to_owned()),
+            ),
+            ("vsock:host:50051", GrpcTargetError::InvalidCid("host".to_owned())),
+            ("vsock:-1:50051", GrpcTargetError::InvalidCid("-1".to_owned())),
+        ];
+
+        for (target, expected) in cases {
+            assert_eq!(target.parse::<GrpcTarget>(), Err(expected), "{target}");
+        }
+    }
+
+    #[test]
+    fn describes_errors() {
+        let cases = [
+            (
+                GrpcTargetError::MissingAddress { scheme: "unix" },
+                "The unix: target has no address",
+            ),
+            (
+                GrpcTargetError::UnsupportedDnsAuthority("8.8.8.8".to_owned()),
+                "The DNS authority \"8.8.8.8\" is not supported in a dns: target",
+            ),
+            (
+                GrpcTargetError::InvalidAddress("local host".to_owned()),
+                "The address \"local host\" in the target is invalid",
+            ),
+            (
+                GrpcTargetError::InvalidPort("http".to_owned()),
+                "The port \"http\" in the target is invalid",
+            ),
+            (
+                GrpcTargetError::InvalidCid("host".to_owned()),
+                "The CID \"host\" in the vsock: target is invalid",
+            ),
+            (
+                GrpcTargetError::TransportNotEnabled {
+                    scheme: "vsock",
+                    feature: "vsock-transport",
+                },
+                "The vsock: target requires the vsock-transport feature of alternate-tonic-client to be enabled",
+            ),
+        ];
+
+        for (err, expected) in cases {
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn builds_connectors_for_enabled_transports() {
+        let cases = [
+            "localhost:50051",
+            "ipv4:127.0.0.1:50051",
+            "ipv6:[::1]:50051",
+            "unix:/run/grpc.sock",
+            "unix-abstract:grpc",
+            "vsock:3:50051",
+            "firecracker:/run/v.sock:52",
+        ];
+
+        for target in cases {
+            assert!(GrpcConnector::from_target(target).is_ok(), "{target}");
+        }
+
+        assert_eq!(
+            GrpcConnector::from_target("vsock:3:port").err(),
+            Some(GrpcTargetError::InvalidPort("port".to_owned()))
+        );
+    }
+
+    #[cfg(not(feature = "vsock-transport"))]
+    #[test]
+    fn reports_disabled_transports() {
+        assert_eq!(
+            GrpcConnector::from_target("vsock:3:50051").err(),
+            Some(GrpcTargetError::TransportNotEnabled {
+                scheme: "vsock",
+                feature: "vsock-transport",
+            })
+        );
+    }
+}
@@ -9,14 +9,20 @@ use crate::dns::DnsResolver;
 
 /// Keepalive options for a TCP connection.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
 pub struct TcpKeepaliveConfig {
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
     pub duration: Duration,
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
     pub interval: Duration,
     pub retries: u32,
 }
 
 /// One or multiple IPv4 and/or IPv6 local addresses to use for a TCP connection.
 #[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum TcpLocalAddress {
     V4(Ipv4Addr),
     V6(Ipv6Addr),
@@ -26,18 +32,23 @@ pub enum TcpLocalAddress {
 /// Configuration for TCP connections. This struct is cheaply [Clone]-able and implements [Default],
 /// as all its settings are optional.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
 pub struct TcpConfig {
     pub keepalive: Option<TcpKeepaliveConfig>,
     pub nodelay: Option<bool>,
     pub send_buffer_size: Option<usize>,
     pub recv_buffer_size: Option<usize>,
     pub local_address: Option<TcpLocalAddress>,
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
     pub internal_timeout: Option<Duration>,
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
     pub happy_eyeballs_timeout: Option<Duration>,
     pub reuse_address: Option<bool>,
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     pub interface: Option<String>,
     #[cfg(target_os = "linux")]
+    #[cfg_attr(feature = "serde", serde(with = "humantime_serde"))]
     pub user_timeout: Option<Duration>,
 }
 
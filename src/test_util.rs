@@ -0,0 +1,178 @@
+//! Fixtures shared by the unit tests of this crate.
+
+#[cfg(feature = "dns-tcp-tls-transport")]
+pub(crate) use tls::*;
+
+#[cfg(feature = "dns-tcp-tls-transport")]
+mod tls {
+    use std::{net::SocketAddr, sync::Arc};
+
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, Issuer, KeyPair, KeyUsagePurpose};
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use crate::{DnsResolver, GrpcConnector, GrpcConnectorBuilder, TcpConfig, TlsConfig};
+
+    /// A certificate authority issuing certificates for tests.
+    pub(crate) struct TestCa {
+        issuer: Issuer<'static, KeyPair>,
+        certificate: CertificateDer<'static>,
+        certificate_pem: String,
+    }
+
+    /// A certificate issued by a [TestCa], along with its private key.
+    pub(crate) struct TestCertificate {
+        pub(crate) certificate: CertificateDer<'static>,
+        pub(crate) certificate_pem: String,
+        key: PrivatePkcs8KeyDer<'static>,
+        pub(crate) key_pem: String,
+    }
+
+    impl TestCa {
+        pub(crate) fn new(name: &str) -> Self {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::DigitalSignature];
+            let certificate = params.self_signed(&key).unwrap();
+
+            Self {
+                issuer: Issuer::new(params, key),
+                certificate: certificate.der().clone(),
+                certificate_pem: certificate.pem(),
+            }
+        }
+
+        pub(crate) fn certificate_pem(&self) -> &str {
+            &self.certificate_pem
+        }
+
+        pub(crate) fn root_store(&self) -> rustls::RootCertStore {
+            let mut root_store = rustls::RootCertStore::empty();
+            root_store.add(self.certificate.clone()).unwrap();
+            root_store
+        }
+
+        /// Issue a certificate for the given DNS names or IP addresses.
+        pub(crate) fn issue(&self, names: &[&str]) -> TestCertificate {
+            let key = KeyPair::generate().unwrap();
+            let params = CertificateParams::new(names.iter().map(|name| name.to_string()).collect::<Vec<_>>()).unwrap();
+            let certificate = params.signed_by(&key, &self.issuer).unwrap();
+
+            TestCertificate {
+                certificate: certificate.der().clone(),
+                certificate_pem: certificate.pem(),
+                key: PrivatePkcs8KeyDer::from(key.serialize_der()),
+                key_pem: key.serialize_pem(),
+            }
+        }
+    }
+
+    impl TestCertificate {
+        pub(crate) fn chain(&self) -> Vec<CertificateDer<'static>> {
+            vec![self.certificate.clone()]
+        }
+
+        pub(crate) fn key(&self) -> PrivateKeyDer<'static> {
+            PrivateKeyDer::Pkcs8(self.key.clone_key())
+        }
+    }
+
+    /// A [rustls::ServerConfig] presenting the given certificate and offering `h2` via ALPN, requiring clients to
+    /// present a certificate issued by the given [TestCa] if any.
+    pub(crate) fn server_config(certificate: &TestCertificate, client_ca: Option<&TestCa>) -> rustls::ServerConfig {
+        let builder = rustls::ServerConfig::builder();
+        let builder = match client_ca {
+            Some(client_ca) => builder.with_client_cert_verifier(
+                rustls::server::WebPkiClientVerifier::builder(Arc::new(client_ca.root_store()))
+                    .build()
+                    .unwrap(),
+            ),
+            None => builder.with_no_client_auth(),
+        };
+
+        let mut config = builder
+            .with_single_cert(certificate.chain(), certificate.key())
+            .unwrap();
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        config
+    }
+
+    /// Spawn a TLS server on a local port, reporting the client certificate presented on every accepted connection
+    /// (or [None] if none was presented) and keeping the connection open until the client closes it.
+    pub(crate) async fn spawn_tls_server(
+        config: rustls::ServerConfig,
+    ) -> (SocketAddr, mpsc::UnboundedReceiver<Option<CertificateDer<'static>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+
+                    let client_certificate = stream
+                        .get_ref()
+                        .1
+                        .peer_certificates()
+                        .and_then(|chain| chain.first())
+                        .map(|certificate| certificate.clone().into_owned());
+                    let _ = sender.send(client_certificate);
+                    let _ = stream.read_to_end(&mut Vec::new()).await;
+                });
+            }
+        });
+
+        (addr, receiver)
+    }
+
+    /// Spawn a plaintext TCP server on a local port, reporting the first bytes received on every accepted connection
+    /// and answering them with the given reply before closing the connection.
+    pub(crate) async fn spawn_plaintext_server(reply: &'static [u8]) -> (SocketAddr, mpsc::UnboundedReceiver<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let sender = sender.clone();
+
+                tokio::spawn(async move {
+                    let mut received = vec![0u8; 4096];
+                    let Ok(len) = stream.read(&mut received).await else {
+                        return;
+                    };
+
+                    received.truncate(len);
+                    let _ = sender.send(received);
+                    let _ = stream.write_all(reply).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        (addr, receiver)
+    }
+
+    /// A [GrpcConnector] to the given local address using the given [TlsConfig].
+    pub(crate) fn tls_connector(addr: SocketAddr, tls_config: TlsConfig) -> GrpcConnector {
+        GrpcConnectorBuilder::new().build_to_tcp_host_with_tls(
+            format!("https://{addr}").parse().unwrap(),
+            DnsResolver::default(),
+            TcpConfig::default(),
+            tls_config,
+        )
+    }
+}
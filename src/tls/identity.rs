@@ -0,0 +1,196 @@
+use std::sync::Arc;
+
+use rustls::{
+    CertificateError, DigitallySignedStruct, Error, OtherError, RootCertStore, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::WebPkiSupportedAlgorithms,
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+
+/// The identity of a TLS server, extracted from the subject alternative names (SANs) of its end-entity certificate.
+/// When server identity verification is configured via [TlsConfig::verify_server_identity](crate::TlsConfig::verify_server_identity),
+/// the verified [PeerIdentity] is inserted into the [ConnectionInfo](crate::ConnectionInfo) of every connection.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PeerIdentity {
+    /// The SPIFFE ID of the server, being its only URI SAN if that SAN uses the `spiffe` scheme.
+    pub spiffe_id: Option<String>,
+    /// All URI SANs of the server.
+    pub uri_sans: Vec<String>,
+    /// All DNS SANs of the server.
+    pub dns_sans: Vec<String>,
+}
+
+impl PeerIdentity {
+    pub(crate) fn from_certificate(certificate: &CertificateDer<'_>) -> Result<Self, Error> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(certificate)
+            .map_err(|_| Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        let subject_alternative_name = certificate
+            .subject_alternative_name()
+            .map_err(|_| Error::InvalidCertificate(CertificateError::BadEncoding))?;
+
+        let mut peer_identity = PeerIdentity::default();
+        for general_name in subject_alternative_name
+            .iter()
+            .flat_map(|extension| extension.value.general_names.iter())
+        {
+            match general_name {
+                x509_parser::extensions::GeneralName::URI(uri) => peer_identity.uri_sans.push(uri.to_string()),
+                x509_parser::extensions::GeneralName::DNSName(dns) => peer_identity.dns_sans.push(dns.to_string()),
+                _ => {}
+            }
+        }
+
+        if let [uri] = peer_identity.uri_sans.as_slice() {
+            if uri.starts_with("spiffe://") {
+                peer_identity.spiffe_id = Some(uri.clone());
+            }
+        }
+
+        Ok(peer_identity)
+    }
+}
+
+/// A matcher for the [PeerIdentity] of a TLS server, used instead of DNS hostname verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerIdentityMatcher {
+    /// Match a server with exactly the given SPIFFE ID, such as `spiffe://trust-domain/ns/x/sa/y`.
+    SpiffeId(String),
+    /// Match any server with a SPIFFE ID in the given trust domain, such as `trust-domain`.
+    SpiffeTrustDomain(String),
+    /// Match a server having the given URI SAN.
+    UriSan(String),
+    /// Match a server having the given DNS SAN.
+    DnsSan(String),
+    /// Match a server matched by any of the given matchers.
+    AnyOf(Vec<ServerIdentityMatcher>),
+}
+
+impl ServerIdentityMatcher {
+    /// Check whether the given [PeerIdentity] is matched by this [ServerIdentityMatcher].
+    pub fn matches(&self, peer_identity: &PeerIdentity) -> bool {
+        match self {
+            ServerIdentityMatcher::SpiffeId(spiffe_id) => peer_identity.spiffe_id.as_ref() == Some(spiffe_id),
+            ServerIdentityMatcher::SpiffeTrustDomain(trust_domain) => {
+                peer_identity.spiffe_id.as_ref().is_some_and(|spiffe_id| {
+                    spiffe_id["spiffe://".len()..]
+                        .split('/')
+                        .next()
+                        .is_some_and(|domain| domain == trust_domain)
+                })
+            }
+            ServerIdentityMatcher::UriSan(uri) => peer_identity.uri_sans.contains(uri),
+            ServerIdentityMatcher::DnsSan(dns) => {
+                peer_identity.dns_sans.iter().any(|san| san.eq_ignore_ascii_case(dns))
+            }
+            ServerIdentityMatcher::AnyOf(matchers) => matchers.iter().any(|matcher| matcher.matches(peer_identity)),
+        }
+    }
+}
+
+/// An error emitted when the identity of a TLS server is not matched by the configured [ServerIdentityMatcher].
+#[derive(Debug, Clone)]
+pub struct ServerIdentityMismatchError {
+    pub peer_identity: PeerIdentity,
+}
+
+impl std::fmt::Display for ServerIdentityMismatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The identity of the TLS server ({:?}) is not matched by the configured matcher",
+            self.peer_identity
+        )
+    }
+}
+
+impl std::error::Error for ServerIdentityMismatchError {}
+
+/// A [ServerCertVerifier] validating the server's certificate chain against a trust bundle and matching the SANs of its
+/// end-entity certificate against a [ServerIdentityMatcher], ignoring the server name used to connect.
+#[derive(Debug)]
+pub(crate) struct ServerIdentityVerifier {
+    matcher: ServerIdentityMatcher,
+    trust_bundle: Arc<RootCertStore>,
+    supported_algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerIdentityVerifier {
+    pub(crate) fn new(
+        matcher: ServerIdentityMatcher,
+        trust_bundle: Arc<RootCertStore>,
+        supported_algorithms: WebPkiSupportedAlgorithms,
+    ) -> Self {
+        Self {
+            matcher,
+            trust_bundle,
+            supported_algorithms,
+        }
+    }
+}
+
+pub(crate) fn map_webpki_error(err: webpki::Error) -> Error {
+    Error::InvalidCertificate(match err {
+        webpki::Error::BadDer | webpki::Error::BadDerTime => CertificateError::BadEncoding,
+        webpki::Error::CertExpired { .. } => CertificateError::Expired,
+        webpki::Error::CertNotValidYet { .. } => CertificateError::NotValidYet,
+        webpki::Error::CertRevoked => CertificateError::Revoked,
+        webpki::Error::UnknownIssuer => CertificateError::UnknownIssuer,
+        webpki::Error::InvalidSignatureForPublicKey => CertificateError::BadSignature,
+        err => CertificateError::Other(OtherError(Arc::new(err))),
+    })
+}
+
+impl ServerCertVerifier for ServerIdentityVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let certificate = webpki::EndEntityCert::try_from(end_entity).map_err(map_webpki_error)?;
+        certificate
+            .verify_for_usage(
+                self.supported_algorithms.all,
+                &self.trust_bundle.roots,
+                intermediates,
+                now,
+                webpki::KeyUsage::server_auth(),
+                None,
+                None,
+            )
+            .map_err(map_webpki_error)?;
+
+        let peer_identity = PeerIdentity::from_certificate(end_entity)?;
+        if !self.matcher.matches(&peer_identity) {
+            return Err(Error::InvalidCertificate(CertificateError::Other(OtherError(
+                Arc::new(ServerIdentityMismatchError { peer_identity }),
+            ))));
+        }
+
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.supported_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.supported_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.supported_algorithms.supported_schemes()
+    }
+}
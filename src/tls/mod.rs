@@ -0,0 +1,644 @@
+mod identity;
+mod pinning;
+
+pub use identity::{PeerIdentity, ServerIdentityMatcher, ServerIdentityMismatchError};
+pub use pinning::{PinMismatchError, PinningMode, SpkiPin, SpkiPinParseError, SpkiPins};
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use http::Uri;
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+use tokio::net::TcpStream;
+use tower::BoxError;
+
+use crate::stream::{ConnectionInfo, GrpcStream, GrpcStreamInner};
+
+type ClientConfigSource = Arc<RwLock<Arc<rustls::ClientConfig>>>;
+
+/// Configuration for TLS connections backed by the [rustls] crate.
+///
+/// `h2` is offered via ALPN in addition to the protocols configured in the [rustls::ClientConfig], being preferred
+/// over them unless they already include it.
+///
+/// A [TlsConfig] either uses a fixed [rustls::ClientConfig] or, when created via [TlsConfig::reloadable], one that can be
+/// swapped at runtime via a [TlsConfigReloader]. Swapping the [rustls::ClientConfig] only affects new connections, while
+/// established connections keep using the configuration they were made with.
+pub struct TlsConfig {
+    source: ClientConfigSource,
+    options: TlsOptions,
+    mode: TlsMode,
+    fallback_hook: Option<FallbackHook>,
+}
+
+type FallbackHook = Arc<dyn Fn(&BoxError) + Send + Sync>;
+
+/// Whether and how TLS is used by the DNS/TCP/TLS transport. The scheme of the [Uri] given to the transport has no
+/// effect on whether TLS is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum TlsMode {
+    /// Always use TLS, failing the connection attempt if the TLS handshake fails.
+    Required,
+    /// Never use TLS, connecting over plaintext TCP.
+    Disabled,
+    /// Try to use TLS and, if the server turns out not to speak TLS, connect again over plaintext TCP. The server is
+    /// considered not to speak TLS if it responds to the TLS handshake with data that is not a TLS record or closes the
+    /// connection without responding, in which case the error of the failed TLS handshake is passed to the hook
+    /// configured via [TlsConfig::on_fallback]. All other TLS errors, such as a server certificate failing verification
+    /// or a [PinMismatchError] or [ServerIdentityMismatchError], fail the connection attempt like with
+    /// [TlsMode::Required]. This mode offers no protection against an attacker able to tamper with the connection.
+    Opportunistic,
+}
+
+/// Options of a [TlsConfig] applied on top of every [rustls::ClientConfig] it uses, including swapped ones.
+#[derive(Debug, Clone, Default)]
+struct TlsOptions {
+    server_identity: Option<(ServerIdentityMatcher, Arc<rustls::RootCertStore>)>,
+    pinning: Option<(SpkiPins, PinningMode)>,
+    session_store: Option<Arc<dyn rustls::client::ClientSessionStore>>,
+    early_data: bool,
+    #[cfg(feature = "tls-key-log")]
+    key_log: Option<Arc<dyn rustls::KeyLog>>,
+}
+
+impl TlsOptions {
+    fn apply(&self, config: &mut rustls::ClientConfig) {
+        if !config.alpn_protocols.iter().any(|protocol| protocol == b"h2") {
+            config.alpn_protocols.insert(0, b"h2".to_vec());
+        }
+
+        if let Some(session_store) = &self.session_store {
+            config.resumption = rustls::client::Resumption::store(session_store.clone());
+        }
+
+        if self.early_data() {
+            config.enable_early_data = true;
+        }
+
+        #[cfg(feature = "tls-key-log")]
+        if let Some(key_log) = &self.key_log {
+            config.key_log = key_log.clone();
+        }
+
+        if let Some((matcher, trust_bundle)) = &self.server_identity {
+            let verifier = identity::ServerIdentityVerifier::new(
+                matcher.clone(),
+                trust_bundle.clone(),
+                config.crypto_provider().signature_verification_algorithms,
+            );
+            config.dangerous().set_certificate_verifier(Arc::new(verifier));
+        }
+
+        if let Some((pins, PinningMode::Exclusive)) = &self.pinning {
+            let verifier =
+                pinning::PinningVerifier::new(pins.clone(), config.crypto_provider().signature_verification_algorithms);
+            config.dangerous().set_certificate_verifier(Arc::new(verifier));
+        }
+    }
+}
+
+impl TlsOptions {
+    fn early_data(&self) -> bool {
+        self.early_data && !matches!(self.pinning, Some((_, PinningMode::WithVerification)))
+    }
+}
+
+/// Details of an established TLS connection, inserted into the [ConnectionInfo] of every connection made with the
+/// DNS/TCP/TLS transport that uses TLS.
+///
+/// For connections sending early data, the handshake is completed after the connection is handed over to HTTP/2, in
+/// which case the negotiated details that are only known once the handshake completes are absent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConnectionInfo {
+    /// The server name sent via SNI and used for verifying the server's certificate.
+    pub server_name: String,
+    /// The negotiated ALPN protocol, which is `h2` for servers supporting ALPN unless other protocols were offered via
+    /// the [rustls::ClientConfig].
+    pub alpn_protocol: Option<Vec<u8>>,
+    /// The negotiated TLS version.
+    pub protocol_version: Option<rustls::ProtocolVersion>,
+    /// The negotiated cipher suite.
+    pub cipher_suite: Option<rustls::CipherSuite>,
+    /// The certificate chain presented by the server, starting with its end-entity certificate.
+    pub peer_certificates: Option<Arc<[rustls::pki_types::CertificateDer<'static>]>>,
+    /// Whether a previous TLS session was resumed. For connections sending early data, this reflects the resumption
+    /// attempt.
+    pub resumed: bool,
+    /// Whether TLS 1.3 early data was offered on this connection.
+    pub early_data: bool,
+}
+
+/// Unwrap the error of a failed TLS handshake into a [PinMismatchError] or [ServerIdentityMismatchError] emitted by
+/// this crate's verifiers, so that they can be told apart from other errors via downcasting.
+fn map_handshake_error(err: std::io::Error) -> BoxError {
+    if let Some(rustls::Error::InvalidCertificate(rustls::CertificateError::Other(rustls::OtherError(inner)))) =
+        err.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>())
+    {
+        if let Some(err) = inner.downcast_ref::<PinMismatchError>() {
+            return Box::new(err.clone());
+        }
+
+        if let Some(err) = inner.downcast_ref::<ServerIdentityMismatchError>() {
+            return Box::new(err.clone());
+        }
+    }
+
+    Box::new(err)
+}
+
+/// Whether the error of a failed TLS handshake shows that the server does not speak TLS, having responded with data
+/// that cannot be parsed as a TLS record or closed the connection without responding.
+pub(crate) fn is_plaintext_server(err: &BoxError) -> bool {
+    let Some(err) = err.downcast_ref::<std::io::Error>() else {
+        return false;
+    };
+
+    match err.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>()) {
+        Some(rustls::Error::InvalidMessage(_)) => true,
+        Some(_) => false,
+        None => matches!(
+            err.kind(),
+            std::io::ErrorKind::UnexpectedEof | std::io::ErrorKind::ConnectionReset
+        ),
+    }
+}
+
+impl TlsConfig {
+    /// Create a new [TlsConfig] from a [rustls::ClientConfig] and a [TlsMode] specifying whether TLS is used.
+    pub fn new(config: rustls::ClientConfig, mode: TlsMode) -> Self {
+        Self {
+            source: Arc::new(RwLock::new(Arc::new(config))),
+            options: TlsOptions::default(),
+            mode,
+            fallback_hook: None,
+        }
+    }
+
+    /// Set a function that is called with the error of the failed TLS handshake whenever a connection falls back to
+    /// plaintext TCP with [TlsMode::Opportunistic], for example to log a warning. The function is not called for TLS
+    /// errors that fail the connection attempt.
+    pub fn on_fallback<F: Fn(&BoxError) + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.fallback_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Authenticate servers by the subject alternative names (SANs) of their certificates, such as a SPIFFE ID, instead
+    /// of by DNS hostname. The server's certificate chain is validated against the given trust bundle (replacing the
+    /// verifier of the [rustls::ClientConfig]) and its end-entity certificate must be matched by the given
+    /// [ServerIdentityMatcher]. The verified [PeerIdentity] is inserted into the [ConnectionInfo] of every connection.
+    pub fn verify_server_identity(
+        mut self,
+        matcher: ServerIdentityMatcher,
+        trust_bundle: rustls::RootCertStore,
+    ) -> Self {
+        self.options.server_identity = Some((matcher, Arc::new(trust_bundle)));
+        self
+    }
+
+    /// Use the given [rustls::client::ClientSessionStore] to store the session tickets and session IDs of TLS sessions,
+    /// so that all connections made via a [GrpcConnector](crate::GrpcConnector) can resume each other's sessions instead
+    /// of each performing a full handshake. Unlike the store of the [rustls::ClientConfig], this store is kept when the
+    /// [rustls::ClientConfig] is swapped via a [TlsConfigReloader].
+    pub fn session_store(mut self, session_store: Arc<dyn rustls::client::ClientSessionStore>) -> Self {
+        self.options.session_store = Some(session_store);
+        self
+    }
+
+    /// Use an in-memory [rustls::client::ClientSessionStore] storing up to the given number of sessions, like
+    /// [TlsConfig::session_store].
+    pub fn session_cache(self, size: usize) -> Self {
+        self.session_store(Arc::new(rustls::client::ClientSessionMemoryCache::new(size)))
+    }
+
+    /// Enable sending TLS 1.3 early data (0-RTT) on resumed connections, which lets requests be sent before the handshake
+    /// completes. Early data can be replayed by an attacker, so this should only be enabled for servers whose RPCs are
+    /// idempotent. Early data is never sent when combined with [PinningMode::WithVerification], as the pins can only be
+    /// checked after the handshake completes.
+    pub fn early_data(mut self, enabled: bool) -> Self {
+        self.options.early_data = enabled;
+        self
+    }
+
+    /// Log the TLS secrets of all connections to the file named by the `SSLKEYLOGFILE` environment variable in the NSS
+    /// key log format, allowing captured gRPC traffic to be decrypted by tools such as Wireshark. Nothing is logged if the
+    /// variable is not set. This must never be enabled in production and is only available with the `tls-key-log`
+    /// feature, which should only be enabled for debugging.
+    #[cfg(feature = "tls-key-log")]
+    pub fn key_log_file(self) -> Self {
+        self.key_log(Arc::new(rustls::KeyLogFile::new()))
+    }
+
+    /// Log the TLS secrets of all connections to a custom [rustls::KeyLog] sink, like [TlsConfig::key_log_file]. This is
+    /// only available with the `tls-key-log` feature, which should only be enabled for debugging.
+    #[cfg(feature = "tls-key-log")]
+    pub fn key_log(mut self, key_log: Arc<dyn rustls::KeyLog>) -> Self {
+        self.options.key_log = Some(key_log);
+        self
+    }
+
+    /// Pin the public keys of servers to the given [SpkiPins], combined with the verification of their certificate
+    /// chains according to the given [PinningMode]. [PinningMode::Exclusive] takes precedence over all other
+    /// verification, including [TlsConfig::verify_server_identity]. When no presented key matches a pin, the connection
+    /// attempt fails with a [PinMismatchError].
+    pub fn pin_public_keys(mut self, pins: SpkiPins, mode: PinningMode) -> Self {
+        self.options.pinning = Some((pins, mode));
+        self
+    }
+
+    /// Create a new [TlsConfig] like [TlsConfig::new], alongside a [TlsConfigReloader] that can later swap the
+    /// [rustls::ClientConfig] used for new connections, for example to rotate a short-lived client certificate.
+    pub fn reloadable(config: rustls::ClientConfig, mode: TlsMode) -> (Self, TlsConfigReloader) {
+        let tls_config = Self::new(config, mode);
+        let reloader = TlsConfigReloader {
+            source: tls_config.source.clone(),
+        };
+
+        (tls_config, reloader)
+    }
+}
+
+/// Paths to PEM-encoded files used for mutual TLS, being the client certificate chain, its private key and optionally
+/// the CA certificates used to verify the server.
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    /// The path to the client certificate chain, starting with the end-entity certificate.
+    pub cert_chain_path: PathBuf,
+    /// The path to the private key of the client certificate, in PKCS#1, PKCS#8 or SEC1 format.
+    pub private_key_path: PathBuf,
+    /// The path to the CA certificates trusted for verifying the server, replacing the verifier of the current
+    /// [rustls::ClientConfig]. Without it, servers keep being verified like before.
+    pub ca_path: Option<PathBuf>,
+}
+
+impl TlsFiles {
+    fn modification_times(&self) -> Vec<Option<SystemTime>> {
+        [
+            Some(&self.cert_chain_path),
+            Some(&self.private_key_path),
+            self.ca_path.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|path| std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok())
+        .collect()
+    }
+}
+
+/// A handle for swapping the [rustls::ClientConfig] of a [TlsConfig] created via [TlsConfig::reloadable]. This struct is
+/// cheaply [Clone]-able.
+#[derive(Clone)]
+pub struct TlsConfigReloader {
+    source: ClientConfigSource,
+}
+
+impl std::fmt::Debug for TlsConfigReloader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConfigReloader").finish_non_exhaustive()
+    }
+}
+
+impl TlsConfigReloader {
+    /// Get the [rustls::ClientConfig] currently used for new connections.
+    pub fn current(&self) -> Arc<rustls::ClientConfig> {
+        self.source.read().expect("TLS config lock was poisoned").clone()
+    }
+
+    /// Swap the [rustls::ClientConfig] used for new connections.
+    pub fn reload(&self, config: rustls::ClientConfig) {
+        *self.source.write().expect("TLS config lock was poisoned") = Arc::new(config);
+    }
+
+    /// Read the given [TlsFiles] and swap in a [rustls::ClientConfig] derived from the current one, with its client
+    /// certificate replaced and, if a CA file is given, its server certificate verifier replaced by a WebPKI verifier
+    /// trusting the CA certificates in that file. The current configuration is left untouched if reading fails.
+    pub fn reload_from_files(&self, files: &TlsFiles) -> Result<(), BoxError> {
+        let mut config = (*self.current()).clone();
+        let provider = config.crypto_provider().clone();
+
+        let cert_chain = CertificateDer::pem_file_iter(&files.cert_chain_path)?.collect::<Result<Vec<_>, _>>()?;
+        let private_key = PrivateKeyDer::from_pem_file(&files.private_key_path)?;
+        let certified_key = rustls::sign::CertifiedKey::from_der(cert_chain, private_key, &provider)?;
+        config.client_auth_cert_resolver = Arc::new(rustls::sign::SingleCertAndKey::from(certified_key));
+
+        if let Some(ca_path) = &files.ca_path {
+            let mut root_store = rustls::RootCertStore::empty();
+            for certificate in CertificateDer::pem_file_iter(ca_path)? {
+                root_store.add(certificate?)?;
+            }
+
+            let verifier =
+                rustls::client::WebPkiServerVerifier::builder_with_provider(Arc::new(root_store), provider).build()?;
+            config.dangerous().set_certificate_verifier(verifier);
+        }
+
+        self.reload(config);
+        Ok(())
+    }
+
+    /// Spawn a [tokio] task that checks the modification times of the given [TlsFiles] at the given interval and calls
+    /// [TlsConfigReloader::reload_from_files] whenever any of them changes. Errors that occur while reloading are passed
+    /// to the given function, and reloading is retried at the next change. The task runs until it is aborted via the
+    /// returned [tokio::task::JoinHandle].
+    pub fn watch_files<F>(self, files: TlsFiles, interval: Duration, on_error: F) -> tokio::task::JoinHandle<()>
+    where
+        F: Fn(BoxError) + Send + 'static,
+    {
+        tokio::task::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let mut modification_times = files.modification_times();
+
+            loop {
+                interval.tick().await;
+
+                let current_modification_times = files.modification_times();
+                if current_modification_times == modification_times {
+                    continue;
+                }
+
+                modification_times = current_modification_times;
+                if let Err(err) = self.reload_from_files(&files) {
+                    on_error(err);
+                }
+            }
+        })
+    }
+}
+
+/// The TLS layer of the DNS/TCP/TLS transport, negotiating TLS on top of an already established (and, if configured,
+/// already handshaked) TCP stream.
+#[derive(Clone)]
+pub(crate) struct TlsConnector {
+    source: ClientConfigSource,
+    options: Arc<TlsOptions>,
+    cache: Arc<Mutex<Option<ClientConfigCache>>>,
+    mode: TlsMode,
+    fallback_hook: Option<FallbackHook>,
+}
+
+/// The effective [rustls::ClientConfig] derived from the last seen source [rustls::ClientConfig], which is only
+/// re-derived when the source is swapped.
+struct ClientConfigCache {
+    source: Arc<rustls::ClientConfig>,
+    effective: Arc<rustls::ClientConfig>,
+}
+
+impl std::fmt::Debug for TlsConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsConnector")
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl TlsConnector {
+    pub(crate) fn new(tls_config: TlsConfig) -> Self {
+        Self {
+            source: tls_config.source,
+            options: Arc::new(tls_config.options),
+            cache: Arc::new(Mutex::new(None)),
+            mode: tls_config.mode,
+            fallback_hook: tls_config.fallback_hook,
+        }
+    }
+
+    pub(crate) fn mode(&self) -> TlsMode {
+        self.mode
+    }
+
+    pub(crate) fn fall_back(&self, err: &BoxError) {
+        if let Some(fallback_hook) = &self.fallback_hook {
+            fallback_hook(err);
+        }
+    }
+
+    pub(crate) fn connect_plaintext(stream: TcpStream, info: ConnectionInfo) -> GrpcStream {
+        GrpcStream {
+            inner: GrpcStreamInner::DnsTcp(TokioIo::new(stream)),
+            info,
+        }
+    }
+
+    fn client_config(&self) -> Arc<rustls::ClientConfig> {
+        let source = self.source.read().expect("TLS config lock was poisoned").clone();
+        let mut cache = self.cache.lock().expect("TLS config cache lock was poisoned");
+
+        if let Some(cache) = cache.as_ref() {
+            if Arc::ptr_eq(&cache.source, &source) {
+                return cache.effective.clone();
+            }
+        }
+
+        let mut effective = (*source).clone();
+        self.options.apply(&mut effective);
+        let effective = Arc::new(effective);
+
+        *cache = Some(ClientConfigCache {
+            source,
+            effective: effective.clone(),
+        });
+        effective
+    }
+
+    pub(crate) async fn connect(
+        &self,
+        uri: &Uri,
+        stream: TcpStream,
+        mut info: ConnectionInfo,
+    ) -> Result<GrpcStream, BoxError> {
+        let host = uri
+            .host()
+            .ok_or("The Uri given to the DNS/TCP/TLS transport has no host to use as a TLS server name")?
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let server_name = rustls::pki_types::ServerName::try_from(host)?.to_owned();
+        let stream = tokio_rustls::TlsConnector::from(self.client_config())
+            .early_data(self.options.early_data())
+            .connect(server_name, stream)
+            .await
+            .map_err(map_handshake_error)?;
+
+        if let Some((pins, PinningMode::WithVerification)) = &self.options.pinning {
+            pins.check(stream.get_ref().1.peer_certificates().unwrap_or_default())
+                .map_err(|err| map_handshake_error(std::io::Error::other(err)))?;
+        }
+
+        let connection = stream.get_ref().1;
+        let early_data = connection.is_handshaking();
+        info.insert(TlsConnectionInfo {
+            server_name: host.to_string(),
+            alpn_protocol: connection.alpn_protocol().map(|protocol| protocol.to_vec()),
+            protocol_version: connection.protocol_version(),
+            cipher_suite: connection.negotiated_cipher_suite().map(|suite| suite.suite()),
+            peer_certificates: connection.peer_certificates().map(Arc::from),
+            resumed: early_data || connection.handshake_kind() == Some(rustls::HandshakeKind::Resumed),
+            early_data,
+        });
+
+        if self.options.server_identity.is_some() {
+            if let Some(end_entity) = connection.peer_certificates().and_then(|chain| chain.first()) {
+                info.insert(PeerIdentity::from_certificate(end_entity)?);
+            }
+        }
+
+        Ok(GrpcStream {
+            inner: GrpcStreamInner::DnsTcpTls(Box::new(TokioIo::new(stream))),
+            info,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::ServiceExt;
+
+    use crate::test_util::{TestCa, server_config, spawn_tls_server, tls_connector};
+
+    use super::*;
+
+    fn alpn_protocols(configured: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut config = rustls::ClientConfig::builder()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth();
+        config.alpn_protocols = configured.iter().map(|protocol| protocol.to_vec()).collect();
+        TlsOptions::default().apply(&mut config);
+        config.alpn_protocols
+    }
+
+    #[test]
+    fn apply_adds_h2_only_if_missing() {
+        assert_eq!(alpn_protocols(&[]), [b"h2".to_vec()]);
+        assert_eq!(alpn_protocols(&[b"http/1.1"]), [b"h2".to_vec(), b"http/1.1".to_vec()]);
+        assert_eq!(
+            alpn_protocols(&[b"http/1.1", b"h2"]),
+            [b"http/1.1".to_vec(), b"h2".to_vec()]
+        );
+    }
+
+    #[tokio::test]
+    async fn negotiates_configured_fallback_protocol() {
+        let ca = TestCa::new("CA");
+        let mut config = server_config(&ca.issue(&["127.0.0.1"]), None);
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let (addr, _client_certificates) = spawn_tls_server(config).await;
+
+        let mut config = rustls::ClientConfig::builder()
+            .with_root_certificates(ca.root_store())
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let stream = tls_connector(addr, TlsConfig::new(config, TlsMode::Required))
+            .oneshot(Uri::from_static("http://localhost"))
+            .await
+            .unwrap();
+
+        let tls_connection_info = stream.connection_info().get::<TlsConnectionInfo>().unwrap();
+        assert_eq!(tls_connection_info.alpn_protocol.as_deref(), Some(&b"http/1.1"[..]));
+    }
+
+    #[tokio::test]
+    async fn reload_from_files_rotates_client_certificate() {
+        let server_ca = TestCa::new("server CA");
+        let client_ca = TestCa::new("client CA");
+        let (addr, mut client_certificates) =
+            spawn_tls_server(server_config(&server_ca.issue(&["127.0.0.1"]), Some(&client_ca))).await;
+
+        let original = client_ca.issue(&["client"]);
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(server_ca.root_store())
+            .with_client_auth_cert(original.chain(), original.key())
+            .unwrap();
+        let (tls_config, reloader) = TlsConfig::reloadable(config, TlsMode::Required);
+        let connector = tls_connector(addr, tls_config);
+
+        let _stream = connector
+            .clone()
+            .oneshot(Uri::from_static("http://localhost"))
+            .await
+            .unwrap();
+        assert_eq!(
+            client_certificates.recv().await.unwrap(),
+            Some(original.certificate.clone())
+        );
+
+        let directory = tempfile::tempdir().unwrap();
+        let rotated = client_ca.issue(&["client"]);
+        let files = TlsFiles {
+            cert_chain_path: directory.path().join("client.pem"),
+            private_key_path: directory.path().join("client.key"),
+            ca_path: None,
+        };
+
+        // A failed reload leaves the current configuration in place
+        assert!(reloader.reload_from_files(&files).is_err());
+        let _stream = connector
+            .clone()
+            .oneshot(Uri::from_static("http://localhost"))
+            .await
+            .unwrap();
+        assert_eq!(
+            client_certificates.recv().await.unwrap(),
+            Some(original.certificate.clone())
+        );
+
+        std::fs::write(&files.cert_chain_path, &rotated.certificate_pem).unwrap();
+        std::fs::write(&files.private_key_path, &rotated.key_pem).unwrap();
+        reloader.reload_from_files(&files).unwrap();
+
+        let _stream = connector
+            .clone()
+            .oneshot(Uri::from_static("http://localhost"))
+            .await
+            .unwrap();
+        assert_eq!(
+            client_certificates.recv().await.unwrap(),
+            Some(rotated.certificate.clone())
+        );
+    }
+
+    #[tokio::test]
+    async fn reload_from_files_replaces_trusted_ca() {
+        let old_ca = TestCa::new("old CA");
+        let new_ca = TestCa::new("new CA");
+        let client_ca = TestCa::new("client CA");
+        let (addr, mut client_certificates) =
+            spawn_tls_server(server_config(&new_ca.issue(&["127.0.0.1"]), Some(&client_ca))).await;
+
+        let client = client_ca.issue(&["client"]);
+        let config = rustls::ClientConfig::builder()
+            .with_root_certificates(old_ca.root_store())
+            .with_client_auth_cert(client.chain(), client.key())
+            .unwrap();
+        let (tls_config, reloader) = TlsConfig::reloadable(config, TlsMode::Required);
+        let connector = tls_connector(addr, tls_config);
+        assert!(
+            connector
+                .clone()
+                .oneshot(Uri::from_static("http://localhost"))
+                .await
+                .is_err()
+        );
+
+        let directory = tempfile::tempdir().unwrap();
+        let files = TlsFiles {
+            cert_chain_path: directory.path().join("client.pem"),
+            private_key_path: directory.path().join("client.key"),
+            ca_path: Some(directory.path().join("ca.pem")),
+        };
+        std::fs::write(&files.cert_chain_path, &client.certificate_pem).unwrap();
+        std::fs::write(&files.private_key_path, &client.key_pem).unwrap();
+        std::fs::write(files.ca_path.as_ref().unwrap(), new_ca.certificate_pem()).unwrap();
+        reloader.reload_from_files(&files).unwrap();
+
+        let _stream = connector.oneshot(Uri::from_static("http://localhost")).await.unwrap();
+        assert_eq!(
+            client_certificates.recv().await.unwrap(),
+            Some(client.certificate.clone())
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use base64::Engine;
+use rustls::{
+    CertificateError, DigitallySignedStruct, Error, OtherError, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::WebPkiSupportedAlgorithms,
+    pki_types::{CertificateDer, ServerName, UnixTime},
+};
+use sha2::{Digest, Sha256};
+
+/// A SHA-256 hash of the DER-encoded SubjectPublicKeyInfo (SPKI) of a certificate, used for public key pinning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpkiPin([u8; 32]);
+
+impl SpkiPin {
+    /// Create a [SpkiPin] from a raw SHA-256 hash.
+    pub fn from_sha256(hash: [u8; 32]) -> Self {
+        Self(hash)
+    }
+
+    /// Create a [SpkiPin] from a base64-encoded SHA-256 hash, the format used by HPKP and `openssl` pipelines such as
+    /// `openssl x509 -pubkey | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64`.
+    pub fn from_base64(hash: &str) -> Result<Self, SpkiPinParseError> {
+        base64::engine::general_purpose::STANDARD
+            .decode(hash)
+            .ok()
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+            .map(Self)
+            .ok_or(SpkiPinParseError)
+    }
+
+    /// Compute the [SpkiPin] of the given DER-encoded certificate.
+    pub fn of_certificate(certificate: &CertificateDer<'_>) -> Result<Self, Error> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(certificate)
+            .map_err(|_| Error::InvalidCertificate(CertificateError::BadEncoding))?;
+        Ok(Self(Sha256::digest(certificate.public_key().raw).into()))
+    }
+}
+
+/// An error emitted when a string is not a valid base64-encoded SHA-256 hash for a [SpkiPin].
+#[derive(Debug, Clone, Copy)]
+pub struct SpkiPinParseError;
+
+impl std::fmt::Display for SpkiPinParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "An SPKI pin must be a base64-encoded SHA-256 hash")
+    }
+}
+
+impl std::error::Error for SpkiPinParseError {}
+
+/// A set of [SpkiPin]s, consisting of the pins of the keys currently in use and backup pins of keys that are not yet in
+/// use, allowing keys to be rotated without locking out clients. A server is accepted if any of its keys matches any pin
+/// of either kind.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SpkiPins {
+    pub pins: Vec<SpkiPin>,
+    pub backup_pins: Vec<SpkiPin>,
+}
+
+impl SpkiPins {
+    /// Create [SpkiPins] from the pins of the keys currently in use and no backup pins.
+    pub fn new<I: IntoIterator<Item = SpkiPin>>(pins: I) -> Self {
+        Self {
+            pins: pins.into_iter().collect(),
+            backup_pins: Vec::new(),
+        }
+    }
+
+    /// Add backup pins of keys that are not yet in use.
+    pub fn backup<I: IntoIterator<Item = SpkiPin>>(mut self, backup_pins: I) -> Self {
+        self.backup_pins.extend(backup_pins);
+        self
+    }
+
+    fn contains(&self, pin: &SpkiPin) -> bool {
+        self.pins.contains(pin) || self.backup_pins.contains(pin)
+    }
+
+    /// Check the given certificates against these [SpkiPins], succeeding if any of them matches.
+    pub(crate) fn check<'a, I>(&self, certificates: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a CertificateDer<'a>>,
+    {
+        let mut certificate_pins = Vec::new();
+        for certificate in certificates {
+            let pin = SpkiPin::of_certificate(certificate)?;
+            if self.contains(&pin) {
+                return Ok(());
+            }
+
+            certificate_pins.push(pin);
+        }
+
+        Err(Error::InvalidCertificate(CertificateError::Other(OtherError(
+            Arc::new(PinMismatchError { certificate_pins }),
+        ))))
+    }
+}
+
+/// How [SpkiPins] configured via [TlsConfig::pin_public_keys](crate::TlsConfig::pin_public_keys) are combined with
+/// the verification of the server's certificate chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinningMode {
+    /// Verify the certificate chain with the verifier of the [rustls::ClientConfig] (usually WebPKI) and additionally
+    /// require any certificate in the presented chain to match a pin, which allows pinning an intermediate CA.
+    WithVerification,
+    /// Replace the verification of the certificate chain with pinning, requiring the key of the server's end-entity
+    /// certificate to match a pin. Certificate validity, issuers and server names are not checked.
+    Exclusive,
+}
+
+/// An error emitted by a TLS connection attempt when none of the keys presented by the server match the configured
+/// [SpkiPins].
+#[derive(Debug, Clone)]
+pub struct PinMismatchError {
+    /// The pins of the keys presented by the server.
+    pub certificate_pins: Vec<SpkiPin>,
+}
+
+impl std::fmt::Display for PinMismatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "None of the keys presented by the TLS server match the configured SPKI pins"
+        )
+    }
+}
+
+impl std::error::Error for PinMismatchError {}
+
+/// A [ServerCertVerifier] accepting any server whose end-entity certificate's key matches a pin, used for
+/// [PinningMode::Exclusive].
+#[derive(Debug)]
+pub(crate) struct PinningVerifier {
+    pins: SpkiPins,
+    supported_algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinningVerifier {
+    pub(crate) fn new(pins: SpkiPins, supported_algorithms: WebPkiSupportedAlgorithms) -> Self {
+        Self {
+            pins,
+            supported_algorithms,
+        }
+    }
+}
+
+impl ServerCertVerifier for PinningVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.pins.check([end_entity])?;
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.supported_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.supported_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.supported_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,196 @@
+/// The reserved CID of the hypervisor, which cannot be connected to by a gRPC client.
+pub const VSOCK_CID_HYPERVISOR: u32 = tokio_vsock::VMADDR_CID_HYPERVISOR;
+
+/// The CID used for local (loopback) communication, requiring the `vsock_loopback` kernel module on Linux.
+pub const VSOCK_CID_LOCAL: u32 = tokio_vsock::VMADDR_CID_LOCAL;
+
+/// The CID of the host, used for connecting from a guest to its host.
+pub const VSOCK_CID_HOST: u32 = tokio_vsock::VMADDR_CID_HOST;
+
+/// The wildcard CID, which is only meaningful when binding and cannot be connected to.
+pub const VSOCK_CID_ANY: u32 = tokio_vsock::VMADDR_CID_ANY;
+
+/// Get the CID of the local machine, as reported by the `/dev/vsock` device.
+pub fn vsock_local_cid() -> Result<u32, VsockConnectError> {
+    vsock::get_local_cid().map_err(VsockConnectError::from_io)
+}
+
+/// An error emitted by a [GrpcConnector](crate::GrpcConnector) using the virtio-vsock transport, distinguishing common
+/// misconfigurations from other I/O errors.
+#[derive(Debug)]
+pub enum VsockConnectError {
+    /// The given CID is reserved and cannot be connected to, being either [VSOCK_CID_HYPERVISOR] or [VSOCK_CID_ANY].
+    ReservedCid(u32),
+    /// No virtio-vsock device is available, usually because the `vhost_vsock`, `virtio_vsock` or `vsock_loopback`
+    /// kernel module is not loaded (`ENODEV`), or because the kernel does not support the vsock address family at all
+    /// (`EAFNOSUPPORT`).
+    NoVsockDevice(std::io::Error),
+    /// The connection was reset, usually because no process listens on the given port (`ECONNRESET`).
+    ConnectionReset(std::io::Error),
+    /// Any other I/O error.
+    Io(std::io::Error),
+}
+
+impl VsockConnectError {
+    pub(crate) fn validate_cid(cid: u32) -> Result<(), Self> {
+        match cid {
+            VSOCK_CID_HYPERVISOR | VSOCK_CID_ANY => Err(Self::ReservedCid(cid)),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn from_io(err: std::io::Error) -> Self {
+        match err.raw_os_error() {
+            Some(libc::ENODEV | libc::EAFNOSUPPORT) => Self::NoVsockDevice(err),
+            Some(libc::ECONNRESET) => Self::ConnectionReset(err),
+            _ => Self::Io(err),
+        }
+    }
+}
+
+impl std::fmt::Display for VsockConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VsockConnectError::ReservedCid(cid) => {
+                write!(f, "The vsock CID {cid} is reserved and cannot be connected to")
+            }
+            VsockConnectError::NoVsockDevice(err) => write!(f, "No vsock device is available: {err}"),
+            VsockConnectError::ConnectionReset(err) => write!(f, "The vsock connection was reset: {err}"),
+            VsockConnectError::Io(err) => write!(f, "A vsock I/O error occurred: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for VsockConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VsockConnectError::ReservedCid(_) => None,
+            VsockConnectError::NoVsockDevice(err)
+            | VsockConnectError::ConnectionReset(err)
+            | VsockConnectError::Io(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper_util::rt::TokioIo;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_vsock::{VsockAddr, VsockListener};
+    use tower::{Service, ServiceExt};
+
+    use crate::GrpcConnectorBuilder;
+
+    use super::*;
+
+    /// Bind a listener on the loopback CID at a port picked by the kernel, or return [None] if the `vsock_loopback`
+    /// kernel module is unavailable, in which case the loopback CID cannot be bound to (`EADDRNOTAVAIL`).
+    fn bind_loopback() -> Option<(VsockListener, u32)> {
+        if !std::path::Path::new("/dev/vsock").exists() {
+            return None;
+        }
+
+        match VsockListener::bind(VsockAddr::new(VSOCK_CID_LOCAL, libc::VMADDR_PORT_ANY)) {
+            Ok(listener) => {
+                let port = listener.local_addr().unwrap().port();
+                Some((listener, port))
+            }
+            Err(err) if err.raw_os_error() == Some(libc::EADDRNOTAVAIL) => None,
+            Err(err) => match VsockConnectError::from_io(err) {
+                VsockConnectError::NoVsockDevice(_) => None,
+                err => panic!("failed to bind a vsock loopback listener: {err}"),
+            },
+        }
+    }
+
+    #[test]
+    fn maps_io_errors() {
+        for (errno, expected) in [
+            (libc::ENODEV, "NoVsockDevice"),
+            (libc::EAFNOSUPPORT, "NoVsockDevice"),
+            (libc::ECONNRESET, "ConnectionReset"),
+            (libc::ECONNREFUSED, "Io"),
+        ] {
+            let err = VsockConnectError::from_io(std::io::Error::from_raw_os_error(errno));
+            let actual = match err {
+                VsockConnectError::ReservedCid(_) => "ReservedCid",
+                VsockConnectError::NoVsockDevice(_) => "NoVsockDevice",
+                VsockConnectError::ConnectionReset(_) => "ConnectionReset",
+                VsockConnectError::Io(_) => "Io",
+            };
+            assert_eq!(actual, expected, "errno {errno}");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_reserved_cids() {
+        for cid in [VSOCK_CID_HYPERVISOR, VSOCK_CID_ANY] {
+            let err = GrpcConnectorBuilder::new()
+                .build_to_vsock_socket(cid, 1234)
+                .oneshot(http::Uri::from_static("http://localhost"))
+                .await
+                .err()
+                .unwrap();
+            assert!(matches!(
+                err.downcast_ref::<VsockConnectError>(),
+                Some(VsockConnectError::ReservedCid(reserved)) if *reserved == cid
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn connects_over_loopback() {
+        let Some((listener, port)) = bind_loopback() else {
+            eprintln!("skipping: vsock loopback is unavailable");
+            return;
+        };
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 4];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request, b"ping");
+            stream.write_all(b"pong").await.unwrap();
+        });
+
+        let mut connector = GrpcConnectorBuilder::new().build_to_vsock_socket(VSOCK_CID_LOCAL, port);
+        let stream = connector
+            .ready()
+            .await
+            .unwrap()
+            .call(http::Uri::from_static("http://localhost"))
+            .await
+            .unwrap();
+        let mut stream = TokioIo::new(stream);
+        stream.write_all(b"ping").await.unwrap();
+
+        let mut response = [0u8; 4];
+        stream.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"pong");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reports_reset_for_unbound_loopback_port() {
+        // Bind and drop a listener to find a port that nothing listens on anymore
+        let Some((listener, port)) = bind_loopback() else {
+            eprintln!("skipping: vsock loopback is unavailable");
+            return;
+        };
+        drop(listener);
+
+        let err = GrpcConnectorBuilder::new()
+            .build_to_vsock_socket(VSOCK_CID_LOCAL, port)
+            .oneshot(http::Uri::from_static("http://localhost"))
+            .await
+            .err()
+            .unwrap();
+        assert!(
+            matches!(
+                err.downcast_ref::<VsockConnectError>(),
+                Some(VsockConnectError::ConnectionReset(_))
+            ),
+            "{err}"
+        );
+    }
+}